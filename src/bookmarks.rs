@@ -0,0 +1,243 @@
+use bevy::{
+    ecs::prelude::*,
+    input::{keyboard::KeyCode, Input},
+    math::Vec3,
+    prelude::{App, Plugin, Transform},
+};
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+use std::{fs, path::{Path, PathBuf}};
+
+use crate::site_map::{SiteMap, SiteMapError, SiteMapStatus};
+use crate::supercamera::{CameraView, FlexibleProjection};
+
+/// The number-row keys that recall the first bookmarks, in order. 2, 3 and
+/// 4 switch between the views.
+const BOOKMARK_KEYS: [(KeyCode, &str); 6] = [
+    (KeyCode::Key5, "5"),
+    (KeyCode::Key6, "6"),
+    (KeyCode::Key7, "7"),
+    (KeyCode::Key8, "8"),
+    (KeyCode::Key9, "9"),
+    (KeyCode::Key0, "0"),
+];
+
+/// A named camera view. Positions are in the building's own frame, metres
+/// from the drawing origin, rather than the editor's re-centred one, so
+/// that bookmarks stay put when vertices move between sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub name: String,
+    #[serde(flatten)]
+    pub view: CameraView,
+}
+
+/// The bookmarks of the current map, kept in `<name>.bookmarks.yaml` next
+/// to its building file.
+#[derive(Default)]
+pub struct Bookmarks {
+    pub open: bool,
+    list: Vec<Bookmark>,
+    /// The map load the list was read for.
+    loads: u64,
+    /// Name for the next bookmark, as typed in the panel.
+    new_name: String,
+}
+
+/// Where the bookmarks of `building_file` are kept. None for maps that were
+/// not loaded from a file.
+fn bookmarks_path(building_file: &str) -> Option<PathBuf> {
+    if building_file.is_empty() {
+        return None;
+    }
+    let stem = building_file.trim_end_matches(".yaml").trim_end_matches(".building");
+    Some(PathBuf::from(format!("{}.bookmarks.yaml", stem)))
+}
+
+fn read_bookmarks(path: &Path) -> Result<Vec<Bookmark>, SiteMapError> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path)
+        .map_err(|source| SiteMapError::Io { filename: path.display().to_string(), source })?;
+    // name the file, so that its errors are not taken for the building's
+    serde_yaml::from_str(&text).map_err(|e| match SiteMapError::from(e) {
+        SiteMapError::Parse { path: location, line, column, message } => SiteMapError::Parse {
+            path: if location.is_empty() {
+                path.display().to_string()
+            } else {
+                format!("{}: {}", path.display(), location)
+            },
+            line,
+            column,
+            message,
+        },
+        other => other,
+    })
+}
+
+fn write_bookmarks(path: &Path, list: &[Bookmark]) -> Result<(), SiteMapError> {
+    let text = serde_yaml::to_string(list).map_err(|e| SiteMapError::Serialize(e.to_string()))?;
+    fs::write(path, text)
+        .map_err(|source| SiteMapError::Io { filename: path.display().to_string(), source })
+}
+
+/// Shifts the positions of a view by `offset`.
+fn shift(view: &CameraView, offset: Vec3) -> CameraView {
+    CameraView {
+        translation: (Vec3::from(view.translation) + offset).into(),
+        orbit_center: (Vec3::from(view.orbit_center) + offset).into(),
+        ..view.clone()
+    }
+}
+
+/// Where the editor's world origin is in the building's own frame.
+fn origin(sm: &SiteMap) -> Vec3 {
+    sm.levels().keys()
+        .find_map(|name| sm.level_transform(name))
+        .map_or(Vec3::ZERO, |xf| Vec3::new(xf.ofs_x as f32, xf.ofs_y as f32, 0.))
+}
+
+/// Reads the bookmarks of each map as it is loaded, lists them in a panel
+/// that can add, recall and delete them, and recalls the first ones from
+/// the number row.
+fn bookmarks_panel(
+    sm: Res<SiteMap>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut status: ResMut<SiteMapStatus>,
+    keyboard_input: Res<Input<KeyCode>>,
+    egui_context: Res<EguiContext>,
+    mut cameras: Query<(&mut Transform, &mut FlexibleProjection)>,
+) {
+    let path = bookmarks_path(sm.filename());
+    if bookmarks.loads != sm.loads() {
+        bookmarks.loads = sm.loads();
+        bookmarks.list = match path.as_deref().map(read_bookmarks).unwrap_or_else(|| Ok(Vec::new())) {
+            Ok(list) => list,
+            Err(e) => {
+                status.error = Some(e.to_string());
+                Vec::new()
+            }
+        };
+    }
+
+    let mut recall = None;
+    if !egui_context.ctx().wants_keyboard_input() {
+        recall = BOOKMARK_KEYS.iter().position(|(key, _)| keyboard_input.just_pressed(*key));
+    }
+
+    let mut add = false;
+    let mut delete = None;
+    if bookmarks.open {
+        let mut open = bookmarks.open;
+        let Bookmarks { list, new_name, .. } = &mut *bookmarks;
+        egui::Window::new("Bookmarks")
+            .open(&mut open)
+            .show(egui_context.ctx(), |ui| {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(new_name);
+                    if ui.add(egui::Button::new("Add").enabled(!new_name.trim().is_empty())).clicked() {
+                        add = true;
+                    }
+                });
+                if path.is_none() {
+                    ui.label("Bookmarks are only kept for maps loaded from a file.");
+                }
+                ui.separator();
+                egui::ScrollArea::from_max_height(300.).show(ui, |ui| {
+                    for (i, bookmark) in list.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            let key = BOOKMARK_KEYS.get(i).map_or("", |(_, label)| *label);
+                            ui.label(key);
+                            if ui.selectable_label(false, &bookmark.name).clicked() {
+                                recall = Some(i);
+                            }
+                            if ui.small_button("✖").clicked() {
+                                delete = Some(i);
+                            }
+                        });
+                    }
+                });
+            });
+        bookmarks.open = open;
+    }
+
+    let (mut transform, mut projection) = cameras.single_mut();
+    let origin = origin(&sm);
+    if let Some(bookmark) = recall.and_then(|i| bookmarks.list.get(i)) {
+        projection.restore(&mut transform, &shift(&bookmark.view, -origin));
+    }
+
+    let changed = if add {
+        let name = bookmarks.new_name.trim().to_string();
+        let view = shift(&projection.view(&transform), origin);
+        // adding a name that is already taken moves that bookmark here
+        match bookmarks.list.iter_mut().find(|b| b.name == name) {
+            Some(bookmark) => bookmark.view = view,
+            None => bookmarks.list.push(Bookmark { name, view }),
+        }
+        bookmarks.new_name.clear();
+        true
+    } else if let Some(i) = delete {
+        bookmarks.list.remove(i);
+        true
+    } else {
+        false
+    };
+    if let (true, Some(path)) = (changed, &path) {
+        if let Err(e) = write_bookmarks(path, &bookmarks.list) {
+            status.error = Some(e.to_string());
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BookmarksPlugin;
+
+impl Plugin for BookmarksPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Bookmarks>()
+           .add_system(bookmarks_panel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::supercamera::ProjectionMode;
+
+    #[test]
+    fn bookmarks_are_kept_next_to_the_building_file() {
+        assert_eq!(bookmarks_path("maps/office.building.yaml"), Some(PathBuf::from("maps/office.bookmarks.yaml")));
+        assert_eq!(bookmarks_path("office.yaml"), Some(PathBuf::from("office.bookmarks.yaml")));
+        assert_eq!(bookmarks_path(""), None);
+    }
+
+    #[test]
+    fn errors_name_the_bookmarks_file() {
+        let path = std::env::temp_dir().join("traffic_editor_iii_test.bookmarks.yaml");
+        fs::write(&path, "- name: home\n  mode: Sideways\n").unwrap();
+        let error = read_bookmarks(&path).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(error.starts_with(&format!("{}: ", path.display())), "{}", error);
+    }
+
+    #[test]
+    fn shifting_there_and_back_keeps_the_view() {
+        let view = CameraView {
+            mode: ProjectionMode::Perspective,
+            translation: [1., 2., 30.],
+            rotation: [0., 0., 0., 1.],
+            ortho_scale: 10.,
+            orbit_center: [1., 2., 0.],
+            orbit_radius: 30.,
+        };
+        let offset = Vec3::new(-12.5, 40., 0.);
+        let shifted = shift(&view, offset);
+        assert_eq!(shifted.translation, [-11.5, 42., 30.]);
+        assert_eq!(shifted.orbit_center, [-11.5, 42., 0.]);
+        assert_eq!((shifted.ortho_scale, shifted.orbit_radius), (10., 30.));
+        assert_eq!(shift(&shifted, -offset), view);
+    }
+}
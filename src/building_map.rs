@@ -0,0 +1,1179 @@
+// Typed document model of the legacy traffic-editor `.building.yaml` format.
+//
+// Everything in here is plain data with no bevy types, so that it can be
+// parsed and checked without a window or a GPU.
+
+use serde::{
+    de::{self, Deserializer, SeqAccess, Visitor},
+    Deserialize, Serialize,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt, fs, io,
+    marker::PhantomData,
+};
+
+/// The parameters of an element, by name. Every element keeps the
+/// parameters it was loaded with, including ones the editor does not know.
+pub type Params = BTreeMap<String, Param>;
+
+/// A parameter value. In the file each one is written as `[type_code, value]`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    String(String),
+    Int(i64),
+    Double(f64),
+    Bool(bool),
+}
+
+impl Param {
+    pub fn type_code(&self) -> u8 {
+        match self {
+            Param::String(_) => 1,
+            Param::Int(_) => 2,
+            Param::Double(_) => 3,
+            Param::Bool(_) => 4,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Param::String(_) => "string",
+            Param::Int(_) => "int",
+            Param::Double(_) => "double",
+            Param::Bool(_) => "bool",
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Param::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Param::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Ints are accepted too, since the files often write e.g. `[3, 90]`.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Param::Double(d) => Some(*d),
+            Param::Int(i) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Param::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for Param {
+    fn from(s: String) -> Param {
+        Param::String(s)
+    }
+}
+
+impl From<&str> for Param {
+    fn from(s: &str) -> Param {
+        Param::String(s.to_string())
+    }
+}
+
+impl From<i64> for Param {
+    fn from(i: i64) -> Param {
+        Param::Int(i)
+    }
+}
+
+impl From<f64> for Param {
+    fn from(d: f64) -> Param {
+        Param::Double(d)
+    }
+}
+
+impl From<bool> for Param {
+    fn from(b: bool) -> Param {
+        Param::Bool(b)
+    }
+}
+
+/// Anything that can go wrong while reading or writing a `.building.yaml` file.
+#[derive(Debug)]
+pub enum SiteMapError {
+    Io {
+        filename: String,
+        source: io::Error,
+    },
+    Parse {
+        /// Location inside the document, e.g. `levels.L1.lanes[3]`.
+        /// Empty when the problem is at the top level.
+        path: String,
+        line: Option<usize>,
+        column: Option<usize>,
+        message: String,
+    },
+    Serialize(String),
+}
+
+impl fmt::Display for SiteMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiteMapError::Io { filename, source } => {
+                write!(f, "could not access [{}]: {}", filename, source)
+            }
+            SiteMapError::Parse { path, line, column, message } => {
+                if !path.is_empty() {
+                    write!(f, "{}: ", path)?;
+                }
+                write!(f, "{}", message)?;
+                if let (Some(line), Some(column)) = (line, column) {
+                    write!(f, " (line {}, column {})", line, column)?;
+                }
+                Ok(())
+            }
+            SiteMapError::Serialize(message) => {
+                write!(f, "could not serialize site map: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SiteMapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SiteMapError::Io { source, .. } => Some(source),
+            SiteMapError::Parse { .. } | SiteMapError::Serialize(_) => None,
+        }
+    }
+}
+
+impl From<serde_yaml::Error> for SiteMapError {
+    fn from(e: serde_yaml::Error) -> Self {
+        let location = e.location();
+        let line = location.as_ref().map(|l| l.line());
+        let column = location.as_ref().map(|l| l.column());
+
+        // serde_yaml reports "<path>: <message> at line L column C", but only
+        // exposes the location programmatically, so peel the rest apart here.
+        let mut message = e.to_string();
+        if let Some(l) = &location {
+            let suffix = format!(" at line {} column {}", l.line(), l.column());
+            if message.ends_with(&suffix) {
+                message.truncate(message.len() - suffix.len());
+            }
+        }
+        let mut path = String::new();
+        if let Some(idx) = message.find(": ") {
+            if !message[..idx].contains(char::is_whitespace) {
+                path = message[..idx].to_string();
+                message = message[idx + 2..].to_string();
+            }
+        }
+
+        SiteMapError::Parse { path, line, column, message }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BuildingMap {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crowd_sim: Option<serde_yaml::Value>,
+    #[serde(default)]
+    pub levels: BTreeMap<String, Level>,
+    #[serde(default)]
+    pub lifts: BTreeMap<String, Lift>,
+    pub name: String,
+    /// The keys the file gave, so that saving does not add empty sections
+    /// it did not have.
+    #[serde(skip)]
+    pub loaded_keys: BTreeSet<String>,
+}
+
+impl BuildingMap {
+    pub fn from_yaml(text: &str) -> Result<BuildingMap, SiteMapError> {
+        let mut building: BuildingMap = serde_yaml::from_str(text)?;
+        let document: serde_yaml::Value = serde_yaml::from_str(text)?;
+        building.loaded_keys = mapping_keys(Some(&document));
+        let levels = document.get("levels");
+        for (name, level) in building.levels.iter_mut() {
+            level.name = name.clone();
+            level.loaded_keys = mapping_keys(levels.and_then(|l| l.get(name.as_str())));
+        }
+        for (name, lift) in building.lifts.iter_mut() {
+            lift.name = name.clone();
+        }
+        Ok(building)
+    }
+
+    /// Reads and parses a `.building.yaml` file.
+    pub fn load(filename: &str) -> Result<BuildingMap, SiteMapError> {
+        let text = fs::read_to_string(filename).map_err(|e| SiteMapError::Io {
+            filename: filename.to_string(),
+            source: e,
+        })?;
+        BuildingMap::from_yaml(&text)
+    }
+
+    /// Writes the map back out in the legacy `.building.yaml` format,
+    /// leaving out empty sections that the file it was loaded from did not
+    /// have.
+    pub fn to_yaml(&self) -> Result<String, SiteMapError> {
+        let error = |e: serde_yaml::Error| SiteMapError::Serialize(e.to_string());
+        let mut document = serde_yaml::to_value(self).map_err(error)?;
+        drop_unloaded_defaults(&mut document, &self.loaded_keys);
+        if let Some(levels) = document.get_mut("levels") {
+            for (name, level) in &self.levels {
+                if let Some(value) = levels.get_mut(name.as_str()) {
+                    drop_unloaded_defaults(value, &level.loaded_keys);
+                }
+            }
+        }
+        serde_yaml::to_string(&document).map_err(error)
+    }
+}
+
+fn mapping_keys(value: Option<&serde_yaml::Value>) -> BTreeSet<String> {
+    value.and_then(|v| v.as_mapping())
+        .map(|m| m.iter().filter_map(|(k, _)| k.as_str().map(String::from)).collect())
+        .unwrap_or_default()
+}
+
+/// Removes the entries of `mapping` that hold an empty list or map, or a
+/// zero, unless their key is one of `loaded_keys`.
+fn drop_unloaded_defaults(mapping: &mut serde_yaml::Value, loaded_keys: &BTreeSet<String>) {
+    use serde_yaml::Value;
+    let mapping = match mapping {
+        Value::Mapping(mapping) => mapping,
+        _ => return,
+    };
+    let dropped: Vec<Value> = mapping.iter()
+        .filter(|(key, value)| {
+            let is_default = match value {
+                Value::Sequence(seq) => seq.is_empty(),
+                Value::Mapping(map) => map.is_empty(),
+                Value::Number(n) => n.as_f64() == Some(0.),
+                _ => false,
+            };
+            is_default && !matches!(key.as_str(), Some(key) if loaded_keys.contains(key))
+        })
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in dropped {
+        mapping.remove(&key);
+    }
+}
+
+/// One floor of the building. Vertex indices used by lanes, walls, doors,
+/// floors and measurements refer to this level's own `vertices`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Level {
+    /// Taken from the key of the `levels` mapping.
+    #[serde(skip)]
+    pub name: String,
+    #[serde(default)]
+    pub doors: Vec<Door>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drawing: Option<Drawing>,
+    #[serde(default)]
+    pub elevation: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub features: Option<serde_yaml::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fiducials: Option<serde_yaml::Value>,
+    #[serde(default)]
+    pub flattened_x_offset: f64,
+    #[serde(default)]
+    pub flattened_y_offset: f64,
+    #[serde(default)]
+    pub floors: Vec<Floor>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub holes: Vec<Floor>,
+    #[serde(default)]
+    pub lanes: Vec<Lane>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layers: Option<serde_yaml::Value>,
+    #[serde(default)]
+    pub measurements: Vec<Edge>,
+    #[serde(default)]
+    pub models: Vec<Model>,
+    #[serde(default)]
+    pub vertices: Vec<Vertex>,
+    #[serde(default)]
+    pub walls: Vec<Wall>,
+    /// The keys the file gave for this level; see `BuildingMap`.
+    #[serde(skip)]
+    pub loaded_keys: BTreeSet<String>,
+}
+
+/// Used for levels that have no measurements to calibrate against.
+pub const DEFAULT_DRAWING_SCALE: f64 = 1.0 / 100.0;
+
+impl Level {
+    /// Metres per drawing pixel: the calibrated scale, or the default for
+    /// levels that have no measurements.
+    pub fn scale(&self) -> f64 {
+        self.drawing_scale().unwrap_or(DEFAULT_DRAWING_SCALE)
+    }
+
+    /// Metres per drawing pixel, fitted by least squares to all of the
+    /// level's measurements. None if there is nothing to fit.
+    pub fn drawing_scale(&self) -> Option<f64> {
+        let mut pixels_metres = 0.;
+        let mut pixels_squared = 0.;
+        for m in &self.measurements {
+            let (a, b) = match (self.vertices.get(m.start), self.vertices.get(m.end)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let distance = match m.params.get("distance").and_then(|d| d.as_f64()) {
+                Some(distance) if distance > 0. => distance,
+                _ => continue,
+            };
+            let pixels = ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt();
+            pixels_metres += pixels * distance;
+            pixels_squared += pixels * pixels;
+        }
+        if pixels_squared > 0. {
+            Some(pixels_metres / pixels_squared)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Drawing {
+    pub filename: String,
+}
+
+/// A floor (or, in `holes`, a cut-out) polygon over the level's vertices.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Floor {
+    #[serde(default)]
+    pub parameters: Params,
+    pub vertices: Vec<usize>,
+}
+
+impl Floor {
+    pub fn texture_name(&self) -> &str {
+        self.parameters.get("texture_name").and_then(|v| v.as_str()).unwrap_or("")
+    }
+
+    /// Rotation of the texture in degrees.
+    pub fn texture_rotation(&self) -> f64 {
+        self.parameters.get("texture_rotation").and_then(|v| v.as_f64()).unwrap_or(0.)
+    }
+
+    /// Size of one texture tile in metres.
+    pub fn texture_scale(&self) -> f64 {
+        self.parameters.get("texture_scale").and_then(|v| v.as_f64()).unwrap_or(1.)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Model {
+    pub model_name: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "static", default = "default_true")]
+    pub static_: bool,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub yaw: f64,
+    #[serde(default)]
+    pub z: f64,
+}
+
+/// An elevator. Its position is given in the drawing coordinates of
+/// `reference_floor_name`; the cabin size and door offsets are in metres.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Lift {
+    /// Taken from the key of the `lifts` mapping.
+    #[serde(skip)]
+    pub name: String,
+    pub depth: f64,
+    #[serde(default)]
+    pub doors: BTreeMap<String, LiftDoor>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub highest_floor: String,
+    #[serde(default)]
+    pub initial_floor_name: String,
+    /// Which of the cabin doors open on each level the lift serves.
+    #[serde(default)]
+    pub level_doors: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub lowest_floor: String,
+    #[serde(default = "default_true")]
+    pub plugins: bool,
+    pub reference_floor_name: String,
+    pub width: f64,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub yaw: f64,
+}
+
+impl Lift {
+    /// Names of the levels this lift stops at or passes through, from the
+    /// lowest to the highest.
+    pub fn served_levels(&self, levels: &BTreeMap<String, Level>) -> Vec<String> {
+        let elevation = |name: &str| levels.get(name).map(|l| l.elevation);
+        let mut served: Vec<&Level> = match (elevation(&self.lowest_floor), elevation(&self.highest_floor)) {
+            (Some(lowest), Some(highest)) => levels.values()
+                .filter(|l| l.elevation >= lowest && l.elevation <= highest)
+                .collect(),
+            _ => self.level_doors.keys().filter_map(|name| levels.get(name)).collect(),
+        };
+        served.sort_by(|a, b| a.elevation.total_cmp(&b.elevation));
+        served.into_iter().map(|l| l.name.clone()).collect()
+    }
+}
+
+/// A cabin door, positioned relative to the cabin centre in the cabin frame.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LiftDoor {
+    #[serde(default)]
+    pub door_type: i64,
+    #[serde(default)]
+    pub motion_axis_orientation: f64,
+    pub width: f64,
+    pub x: f64,
+    pub y: f64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A vertex is stored as `[x, y, z, name]` or `[x, y, z, name, {params}]`.
+///
+/// The waypoint roles of a vertex live in its parameters, and are read and
+/// written through the accessors below. They are left in the map, rather
+/// than moved into fields, so that a file is saved with exactly the keys it
+/// was loaded with.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Vertex {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub name: String,
+    pub params: Params,
+}
+
+/// Vertex parameters that mark a waypoint role; all bools.
+pub const VERTEX_FLAGS: [&str; 3] = ["is_charger", "is_parking_spot", "is_holding_point"];
+
+/// Vertex parameters that name robots or workcells; all strings.
+pub const VERTEX_NAMES: [&str; 4] =
+    ["spawn_robot_type", "spawn_robot_name", "dropoff_ingestor", "pickup_dispenser"];
+
+impl Vertex {
+    /// A flag from `VERTEX_FLAGS`; false when it is not set.
+    pub fn flag(&self, key: &str) -> bool {
+        self.params.get(key).and_then(|p| p.as_bool()).unwrap_or(false)
+    }
+
+    pub fn set_flag(&mut self, key: &str, value: bool) {
+        if value || self.params.contains_key(key) {
+            self.params.insert(key.to_string(), Param::Bool(value));
+        }
+    }
+
+    /// A name from `VERTEX_NAMES`; empty when it is not set.
+    pub fn text(&self, key: &str) -> &str {
+        self.params.get(key).and_then(|p| p.as_str()).unwrap_or("")
+    }
+
+    pub fn set_text(&mut self, key: &str, value: &str) {
+        if !value.is_empty() || self.params.contains_key(key) {
+            self.params.insert(key.to_string(), Param::String(value.to_string()));
+        }
+    }
+
+    pub fn is_charger(&self) -> bool {
+        self.flag("is_charger")
+    }
+
+    pub fn is_parking_spot(&self) -> bool {
+        self.flag("is_parking_spot")
+    }
+
+    pub fn is_holding_point(&self) -> bool {
+        self.flag("is_holding_point")
+    }
+
+    pub fn spawn_robot_type(&self) -> &str {
+        self.text("spawn_robot_type")
+    }
+
+    pub fn spawn_robot_name(&self) -> &str {
+        self.text("spawn_robot_name")
+    }
+
+    pub fn dropoff_ingestor(&self) -> &str {
+        self.text("dropoff_ingestor")
+    }
+
+    pub fn pickup_dispenser(&self) -> &str {
+        self.text("pickup_dispenser")
+    }
+
+    /// Checks that the role parameters have the types the rest of the
+    /// toolchain expects.
+    fn check_params(&self) -> Result<(), String> {
+        for key in VERTEX_FLAGS.iter() {
+            match self.params.get(*key) {
+                Some(p) if p.as_bool().is_none() => {
+                    return Err(format!("vertex {} must be a bool, not {}", key, p.type_name()));
+                }
+                _ => (),
+            }
+        }
+        for key in VERTEX_NAMES.iter() {
+            match self.params.get(*key) {
+                Some(p) if p.as_str().is_none() => {
+                    return Err(format!("vertex {} must be a string, not {}", key, p.type_name()));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lanes, walls, doors and measurements are all stored as
+/// `[start, end, {params}]`, where start and end index the level's vertices.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Edge {
+    pub start: usize,
+    pub end: usize,
+    pub params: Params,
+}
+
+pub type Wall = Edge;
+
+/// Which way a robot has to face while it drives along a lane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LaneOrientation {
+    Any,
+    Forward,
+    Backward,
+}
+
+impl LaneOrientation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LaneOrientation::Any => "",
+            LaneOrientation::Forward => "forward",
+            LaneOrientation::Backward => "backward",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<LaneOrientation> {
+        match s {
+            "" => Some(LaneOrientation::Any),
+            "forward" => Some(LaneOrientation::Forward),
+            "backward" => Some(LaneOrientation::Backward),
+            _ => None,
+        }
+    }
+}
+
+/// A lane of a navigation graph, from `start` to `end`. Like a door, it is
+/// written to the file as an edge with its properties in the parameter map.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(into = "Edge")]
+pub struct Lane {
+    pub start: usize,
+    pub end: usize,
+    /// One-way lanes can only be driven from `start` to `end`.
+    pub bidirectional: bool,
+    /// The navigation graph the lane belongs to, so that fleets of
+    /// different vendors can be given separate graphs.
+    pub graph_idx: i64,
+    pub orientation: LaneOrientation,
+    /// In metres per second; None for no limit.
+    pub speed_limit: Option<f64>,
+    pub demo_mock_floor_name: String,
+    pub demo_mock_lift_name: String,
+    /// Parameters not covered above.
+    pub params: Params,
+    /// Which of `LANE_KEYS` the file gave. Only those, and any whose field
+    /// differs from its default, are written back.
+    pub loaded_keys: BTreeSet<&'static str>,
+}
+
+impl Lane {
+    /// A new two-way lane in graph 0.
+    pub fn new(start: usize, end: usize) -> Lane {
+        Lane {
+            start,
+            end,
+            bidirectional: true,
+            graph_idx: 0,
+            orientation: LaneOrientation::Any,
+            speed_limit: None,
+            demo_mock_floor_name: String::new(),
+            demo_mock_lift_name: String::new(),
+            params: Params::new(),
+            loaded_keys: BTreeSet::new(),
+        }
+    }
+}
+
+/// The parameters the fields of a lane are written to.
+pub const LANE_KEYS: [&str; 6] = [
+    "bidirectional",
+    "demo_mock_floor_name",
+    "demo_mock_lift_name",
+    "graph_idx",
+    "orientation",
+    "speed_limit",
+];
+
+impl TryFrom<Edge> for Lane {
+    type Error = String;
+
+    fn try_from(edge: Edge) -> Result<Lane, String> {
+        let mut params = edge.params;
+        let loaded_keys = LANE_KEYS.iter().copied().filter(|key| params.contains_key(*key)).collect();
+        let mut take_string = |key: &str| -> Result<String, String> {
+            params.remove(key)
+                .map(|v| v.as_str().map(String::from).ok_or_else(|| format!("lane {} must be a string", key)))
+                .transpose()
+                .map(|s| s.unwrap_or_default())
+        };
+        let demo_mock_floor_name = take_string("demo_mock_floor_name")?;
+        let demo_mock_lift_name = take_string("demo_mock_lift_name")?;
+        let orientation = take_string("orientation")?;
+        let orientation = LaneOrientation::from_name(&orientation)
+            .ok_or_else(|| format!("unknown lane orientation [{}]", orientation))?;
+        let bidirectional = params.remove("bidirectional")
+            .map(|v| v.as_bool().ok_or("lane bidirectional must be a bool"))
+            .transpose()?
+            .unwrap_or(true);
+        let graph_idx = params.remove("graph_idx")
+            .map(|v| v.as_i64().ok_or("lane graph_idx must be an integer"))
+            .transpose()?
+            .unwrap_or(0);
+        let speed_limit = params.remove("speed_limit")
+            .map(|v| v.as_f64().ok_or("lane speed_limit must be a number"))
+            .transpose()?;
+
+        Ok(Lane {
+            start: edge.start,
+            end: edge.end,
+            bidirectional,
+            graph_idx,
+            orientation,
+            speed_limit,
+            demo_mock_floor_name,
+            demo_mock_lift_name,
+            params,
+            loaded_keys,
+        })
+    }
+}
+
+impl From<Lane> for Edge {
+    fn from(lane: Lane) -> Edge {
+        let Lane {
+            start,
+            end,
+            bidirectional,
+            graph_idx,
+            orientation,
+            speed_limit,
+            demo_mock_floor_name,
+            demo_mock_lift_name,
+            mut params,
+            loaded_keys,
+        } = lane;
+        let mut put = |key: &'static str, value: Param, default: Param| {
+            if value != default || loaded_keys.contains(key) {
+                params.insert(key.into(), value);
+            }
+        };
+        put("bidirectional", Param::from(bidirectional), Param::from(true));
+        put("demo_mock_floor_name", Param::from(demo_mock_floor_name), Param::from(""));
+        put("demo_mock_lift_name", Param::from(demo_mock_lift_name), Param::from(""));
+        put("graph_idx", Param::from(graph_idx), Param::from(0));
+        put("orientation", Param::from(orientation.as_str()), Param::from(""));
+        if let Some(speed_limit) = speed_limit {
+            params.insert("speed_limit".into(), Param::from(speed_limit));
+        }
+        Edge { start, end, params }
+    }
+}
+
+/// The per-level list an element is stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ElementKind {
+    Vertex,
+    Lane,
+    Wall,
+    Door,
+    Measurement,
+    Floor,
+}
+
+/// Identifies one element of a level by its position in the level's list.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ElementId {
+    pub level: String,
+    pub kind: ElementKind,
+    pub index: usize,
+}
+
+impl ElementId {
+    pub fn new(level: &str, kind: ElementKind, index: usize) -> Self {
+        ElementId { level: level.to_string(), kind, index }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DoorType {
+    Hinged,
+    DoubleHinged,
+    Sliding,
+    DoubleSliding,
+}
+
+impl DoorType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DoorType::Hinged => "hinged",
+            DoorType::DoubleHinged => "double_hinged",
+            DoorType::Sliding => "sliding",
+            DoorType::DoubleSliding => "double_sliding",
+        }
+    }
+
+    pub fn from_name(s: &str) -> Option<DoorType> {
+        match s {
+            "hinged" => Some(DoorType::Hinged),
+            "double_hinged" => Some(DoorType::DoubleHinged),
+            "sliding" => Some(DoorType::Sliding),
+            "double_sliding" => Some(DoorType::DoubleSliding),
+            _ => None,
+        }
+    }
+
+    pub fn is_double(&self) -> bool {
+        matches!(self, DoorType::DoubleHinged | DoorType::DoubleSliding)
+    }
+
+    pub fn is_sliding(&self) -> bool {
+        matches!(self, DoorType::Sliding | DoorType::DoubleSliding)
+    }
+}
+
+/// Which end of a single door carries the hinge (or the slide target).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MotionAxis {
+    Start,
+    End,
+}
+
+/// A door spans the two vertices it is stored with. It is written to the file
+/// as an edge, with its properties in the parameter map.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(into = "Edge")]
+pub struct Door {
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+    pub door_type: DoorType,
+    pub motion_axis: MotionAxis,
+    pub motion_degrees: f64,
+    /// 1 or -1: which side of the door line a hinged door swings into.
+    pub motion_direction: i64,
+    /// Width of the right panel of a double door relative to the left one.
+    pub right_left_ratio: f64,
+    /// Parameters not covered above, e.g. `plugin`.
+    pub params: Params,
+    /// Which of `DOOR_KEYS` the file gave; see `Lane`.
+    pub loaded_keys: BTreeSet<&'static str>,
+}
+
+/// The parameters the fields of a door are written to.
+pub const DOOR_KEYS: [&str; 6] =
+    ["name", "type", "motion_axis", "motion_degrees", "motion_direction", "right_left_ratio"];
+
+impl TryFrom<Edge> for Door {
+    type Error = String;
+
+    fn try_from(edge: Edge) -> Result<Door, String> {
+        let mut params = edge.params;
+        let loaded_keys = DOOR_KEYS.iter().copied().filter(|key| params.contains_key(*key)).collect();
+        let name = params.remove("name")
+            .map(|v| v.as_str().map(String::from).ok_or("door name must be a string"))
+            .transpose()?
+            .unwrap_or_default();
+        let door_type = match params.remove("type") {
+            Some(v) => {
+                let s = v.as_str().ok_or("door type must be a string")?;
+                DoorType::from_name(s).ok_or_else(|| format!("unknown door type [{}]", s))?
+            }
+            None => DoorType::Hinged,
+        };
+        let motion_axis = match params.remove("motion_axis") {
+            Some(v) => match v.as_str() {
+                Some("start") => MotionAxis::Start,
+                Some("end") => MotionAxis::End,
+                _ => return Err(format!("unknown door motion_axis {:?}", v)),
+            },
+            None => MotionAxis::Start,
+        };
+        let motion_degrees = params.remove("motion_degrees")
+            .map(|v| v.as_f64().ok_or("door motion_degrees must be a number"))
+            .transpose()?
+            .unwrap_or(90.);
+        let motion_direction = params.remove("motion_direction")
+            .map(|v| v.as_i64().ok_or("door motion_direction must be an integer"))
+            .transpose()?
+            .unwrap_or(1);
+        let right_left_ratio = params.remove("right_left_ratio")
+            .map(|v| v.as_f64().ok_or("door right_left_ratio must be a number"))
+            .transpose()?
+            .unwrap_or(1.);
+
+        Ok(Door {
+            start: edge.start,
+            end: edge.end,
+            name,
+            door_type,
+            motion_axis,
+            motion_degrees,
+            motion_direction,
+            right_left_ratio,
+            params,
+            loaded_keys,
+        })
+    }
+}
+
+impl From<Door> for Edge {
+    fn from(door: Door) -> Edge {
+        let Door {
+            start,
+            end,
+            name,
+            door_type,
+            motion_axis,
+            motion_degrees,
+            motion_direction,
+            right_left_ratio,
+            mut params,
+            loaded_keys,
+        } = door;
+        let mut put = |key: &'static str, value: Param, default: Param| {
+            if value != default || loaded_keys.contains(key) {
+                params.insert(key.into(), value);
+            }
+        };
+        let axis = match motion_axis {
+            MotionAxis::Start => "start",
+            MotionAxis::End => "end",
+        };
+        put("name", Param::from(name), Param::from(""));
+        put("type", Param::from(door_type.as_str()), Param::from("hinged"));
+        put("motion_axis", Param::from(axis), Param::from("start"));
+        put("motion_degrees", Param::from(motion_degrees), Param::from(90.));
+        put("motion_direction", Param::from(motion_direction), Param::from(1));
+        put("right_left_ratio", Param::from(right_left_ratio), Param::from(1.));
+        Edge { start, end, params }
+    }
+}
+
+impl Serialize for Param {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Param::String(s) => (1, s).serialize(serializer),
+            Param::Int(i) => (2, i).serialize(serializer),
+            Param::Double(d) => (3, d).serialize(serializer),
+            Param::Bool(b) => (4, b).serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Param {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ParamVisitor;
+
+        impl<'de> Visitor<'de> for ParamVisitor {
+            type Value = Param;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a parameter [type_code, value]")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Param, A::Error> {
+                let type_code: u8 = seq.next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let missing = || de::Error::invalid_length(1, &self);
+                let param = match type_code {
+                    1 => Param::String(seq.next_element()?.ok_or_else(missing)?),
+                    2 => Param::Int(seq.next_element()?.ok_or_else(missing)?),
+                    3 => Param::Double(seq.next_element()?.ok_or_else(missing)?),
+                    4 => Param::Bool(seq.next_element()?.ok_or_else(missing)?),
+                    other => {
+                        return Err(de::Error::custom(format!(
+                            "unknown parameter type code {}, expected 1 (string), 2 (int), \
+                             3 (double) or 4 (bool)", other)));
+                    }
+                };
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(3, &self));
+                }
+                Ok(param)
+            }
+        }
+
+        deserializer.deserialize_seq(ParamVisitor)
+    }
+}
+
+impl Serialize for Vertex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+        let len = if self.params.is_empty() { 4 } else { 5 };
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        seq.serialize_element(&self.x)?;
+        seq.serialize_element(&self.y)?;
+        seq.serialize_element(&self.z)?;
+        seq.serialize_element(&self.name)?;
+        if !self.params.is_empty() {
+            seq.serialize_element(&self.params)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Vertex {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VertexVisitor;
+
+        impl<'de> Visitor<'de> for VertexVisitor {
+            type Value = Vertex;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a vertex sequence [x, y, z, name, {params}]")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vertex, A::Error> {
+                let x = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let y = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let z = seq.next_element()?.unwrap_or_default();
+                let name = seq.next_element()?.unwrap_or_default();
+                let params = seq.next_element()?.unwrap_or_default();
+                if seq.next_element::<de::IgnoredAny>()?.is_some() {
+                    return Err(de::Error::invalid_length(6, &self));
+                }
+                let vertex = Vertex { x, y, z, name, params };
+                vertex.check_params().map_err(de::Error::custom)?;
+                Ok(vertex)
+            }
+        }
+
+        deserializer.deserialize_seq(VertexVisitor)
+    }
+}
+
+impl Serialize for Edge {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.start, self.end, &self.params).serialize(serializer)
+    }
+}
+
+/// Reads an edge and converts it to a `T` before the deserializer leaves
+/// the edge, so that conversion errors are reported with the edge's index.
+struct EdgeVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for EdgeVisitor<T>
+where
+    T: TryFrom<Edge>,
+    T::Error: fmt::Display,
+{
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an edge sequence [start, end, {params}]")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<T, A::Error> {
+        let start = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let end = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let params = seq.next_element()?.unwrap_or_default();
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(4, &self));
+        }
+        T::try_from(Edge { start, end, params }).map_err(de::Error::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Edge {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(EdgeVisitor(PhantomData))
+    }
+}
+
+impl<'de> Deserialize<'de> for Lane {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(EdgeVisitor(PhantomData))
+    }
+}
+
+impl<'de> Deserialize<'de> for Door {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(EdgeVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml::Value;
+
+    /// Numbers are compared as f64 so that e.g. `elevation: 0` and
+    /// `elevation: 0.0` count as the same document.
+    fn normalize(value: Value) -> Value {
+        match value {
+            Value::Number(n) => Value::from(n.as_f64().unwrap()),
+            Value::Sequence(seq) => Value::Sequence(seq.into_iter().map(normalize).collect()),
+            Value::Mapping(map) => Value::Mapping(
+                map.into_iter().map(|(k, v)| (normalize(k), normalize(v))).collect(),
+            ),
+            other => other,
+        }
+    }
+
+    #[test]
+    fn office_round_trip() {
+        let original = include_str!("../assets/demo_maps/office.building.yaml");
+        let saved = BuildingMap::from_yaml(original).unwrap().to_yaml().unwrap();
+
+        let before: Value = serde_yaml::from_str(original).unwrap();
+        let after: Value = serde_yaml::from_str(&saved).unwrap();
+        assert_eq!(normalize(before), normalize(after));
+    }
+
+    /// A file that leaves out the optional sections and parameters is saved
+    /// without them, rather than with their defaults filled in.
+    #[test]
+    fn minimal_round_trip() {
+        let original = "\
+levels:
+  L1:
+    lanes:
+      - [0, 1, {}]
+      - [1, 0, {bidirectional: [4, false], graph_idx: [2, 0]}]
+    vertices:
+      - [0, 0, 0, a]
+      - [100, 0, 0, b]
+    walls:
+      - [0, 1, {}]
+name: minimal
+";
+        let building = BuildingMap::from_yaml(original).unwrap();
+        let saved = building.to_yaml().unwrap();
+        let before: Value = serde_yaml::from_str(original).unwrap();
+        let after: Value = serde_yaml::from_str(&saved).unwrap();
+        assert_eq!(normalize(before), normalize(after), "{}", saved);
+
+        // fields changed from their defaults are written even so
+        let mut building = building;
+        let level = building.levels.get_mut("L1").unwrap();
+        level.lanes[0].graph_idx = 2;
+        level.doors.push(Door::try_from(Edge { start: 0, end: 1, params: Params::new() }).unwrap());
+        let saved: Value = serde_yaml::from_str(&building.to_yaml().unwrap()).unwrap();
+        let level = &saved["levels"]["L1"];
+        assert_eq!(level["lanes"][0][2], serde_yaml::from_str::<Value>("{graph_idx: [2, 2]}").unwrap());
+        assert_eq!(level["doors"][0][2], serde_yaml::from_str::<Value>("{}").unwrap());
+    }
+
+    #[test]
+    fn params_are_type_code_value_pairs() {
+        let params: Params = serde_yaml::from_str(
+            "{a: [1, text], b: [2, 3], c: [3, 0.5], d: [4, true]}").unwrap();
+        assert_eq!(params["a"], Param::String("text".into()));
+        assert_eq!(params["b"], Param::Int(3));
+        assert_eq!(params["c"], Param::Double(0.5));
+        assert_eq!(params["d"], Param::Bool(true));
+
+        let written: Value = serde_yaml::from_str(&serde_yaml::to_string(&params).unwrap()).unwrap();
+        let expected: Value = serde_yaml::from_str(
+            "{a: [1, text], b: [2, 3], c: [3, 0.5], d: [4, true]}").unwrap();
+        assert_eq!(written, expected);
+
+        assert!(serde_yaml::from_str::<Params>("{a: [7, 1]}").is_err());
+        assert!(serde_yaml::from_str::<Params>("{a: [2, abc]}").is_err());
+        assert!(serde_yaml::from_str::<Params>("{a: [2, 1, 2]}").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_kept() {
+        let original = "\
+name: b
+levels:
+  L1:
+    doors:
+      - [0, 1, {name: [1, d1], plugin: [1, normal], type: [1, sliding]}]
+    lanes:
+      - [0, 1, {bidirectional: [4, false], robot_hint: [2, 7]}]
+    vertices:
+      - [0, 0, 0, a, {custom: [3, 1.5]}]
+      - [10, 0, 0, b]
+    walls:
+      - [0, 1, {alpha: [3, 1], texture_name: [1, default]}]
+    layers:
+      overlay: {color: [1, 0, 0, 0.5], filename: overlay.png}
+";
+        let building = BuildingMap::from_yaml(original).unwrap();
+        let level = &building.levels["L1"];
+        assert_eq!(level.doors[0].params["plugin"], Param::from("normal"));
+        assert_eq!(level.lanes[0].params["robot_hint"], Param::Int(7));
+        assert_eq!(level.vertices[0].params["custom"], Param::Double(1.5));
+        assert_eq!(level.walls[0].params["texture_name"], Param::from("default"));
+
+        let saved: Value = serde_yaml::from_str(&building.to_yaml().unwrap()).unwrap();
+        let saved = &saved["levels"]["L1"];
+        assert_eq!(saved["doors"][0][2]["plugin"], serde_yaml::from_str::<Value>("[1, normal]").unwrap());
+        assert_eq!(saved["lanes"][0][2]["robot_hint"], serde_yaml::from_str::<Value>("[2, 7]").unwrap());
+        assert_eq!(saved["layers"]["overlay"]["filename"], Value::from("overlay.png"));
+    }
+
+    #[test]
+    fn element_errors_name_the_element() {
+        let e = BuildingMap::from_yaml("\
+name: b
+levels:
+  L1:
+    lanes:
+      - [0, 1]
+      - [0, 1, {graph_idx: [1, two]}]
+").unwrap_err();
+        match e {
+            SiteMapError::Parse { path, message, .. } => {
+                assert_eq!(path, "levels.L1.lanes[1]");
+                assert_eq!(message, "lane graph_idx must be an integer");
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+
+        let e = BuildingMap::from_yaml(
+            "name: b\nlevels:\n  L1:\n    doors:\n      - [0, 1, {type: [1, revolving]}]\n").unwrap_err();
+        assert!(e.to_string().starts_with("levels.L1.doors[0]: unknown door type [revolving]"), "{}", e);
+    }
+}
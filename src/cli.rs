@@ -0,0 +1,217 @@
+// The command line of the `traffic_editor_iii` binary. Everything except
+// `edit` runs headless: no window or GPU context is created, so building
+// files can be processed on CI machines.
+
+use std::path::Path;
+
+use crate::building_map::{BuildingMap, SiteMapError};
+use crate::glb;
+use crate::nav_graph;
+use crate::sdf;
+use crate::validate::{validate, Severity};
+
+pub const USAGE: &str = "\
+usage:
+  traffic_editor_iii [edit] [<building.yaml>]
+      open the editor, on the demo map if no file is given
+  traffic_editor_iii validate <building.yaml>
+      check a building file and report any problems; fails if there are errors
+  traffic_editor_iii export-navgraph <building.yaml> <output_dir>
+      write one <graph_idx>.yaml per navigation graph
+  traffic_editor_iii export-world <building.yaml> <output_dir>
+      write an SDF world of the building, with its level models in <output_dir>/models
+  traffic_editor_iii convert <input> <output>
+      rewrite a building file in the format given by the output's extension:
+      .building.yaml, or .glb for a 3D model of the site with its lanes
+  traffic_editor_iii help
+      show this message";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Edit { filename: Option<String> },
+    Validate { filename: String },
+    ExportNavGraph { filename: String, output_dir: String },
+    ExportWorld { filename: String, output_dir: String },
+    Convert { input: String, output: String },
+    Help,
+}
+
+impl Command {
+    /// Parses the arguments after the program name. A lone argument that is
+    /// not a subcommand is taken as a file to edit, as before subcommands
+    /// existed.
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        let command = match args.as_slice() {
+            [] => Command::Edit { filename: None },
+            ["help"] | ["-h"] | ["--help"] => Command::Help,
+            ["edit"] => Command::Edit { filename: None },
+            ["edit", filename] => Command::Edit { filename: Some(filename.to_string()) },
+            ["validate", filename] => Command::Validate { filename: filename.to_string() },
+            ["export-navgraph", filename, output_dir] => Command::ExportNavGraph {
+                filename: filename.to_string(),
+                output_dir: output_dir.to_string(),
+            },
+            ["export-world", filename, output_dir] => Command::ExportWorld {
+                filename: filename.to_string(),
+                output_dir: output_dir.to_string(),
+            },
+            ["convert", input, output] => Command::Convert {
+                input: input.to_string(),
+                output: output.to_string(),
+            },
+            [subcommand, ..] if is_subcommand(subcommand) => {
+                return Err(format!("wrong arguments for [{}]", subcommand));
+            }
+            [filename] if !filename.starts_with('-') => Command::Edit { filename: Some(filename.to_string()) },
+            [first, ..] => return Err(format!("unknown command [{}]", first)),
+        };
+        Ok(command)
+    }
+
+    pub fn is_headless(&self) -> bool {
+        !matches!(self, Command::Edit { .. })
+    }
+}
+
+fn is_subcommand(arg: &str) -> bool {
+    matches!(arg, "help" | "edit" | "validate" | "export-navgraph" | "export-world" | "convert")
+}
+
+/// Runs a headless command and returns the process exit code: 0 on
+/// success, 1 if the command failed, 2 if it was used wrongly.
+pub fn run_headless(command: &Command) -> i32 {
+    let result = match command {
+        Command::Edit { .. } => {
+            eprintln!("the editor cannot run headless");
+            return 2;
+        }
+        Command::Help => {
+            println!("{}", USAGE);
+            return 0;
+        }
+        Command::Validate { filename } => check(filename),
+        Command::ExportNavGraph { filename, output_dir } => export_nav_graphs(filename, output_dir),
+        Command::ExportWorld { filename, output_dir } => export_world(filename, output_dir),
+        Command::Convert { input, output } => convert(input, output),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+fn load(filename: &str) -> Result<BuildingMap, String> {
+    BuildingMap::load(filename).map_err(|e| format!("{}: {}", filename, e))
+}
+
+fn check(filename: &str) -> Result<(), String> {
+    let diagnostics = validate(&load(filename)?);
+    for diagnostic in &diagnostics {
+        println!("{}: {}", filename, diagnostic);
+    }
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    if errors > 0 {
+        return Err(format!("{}: {} errors, {} warnings", filename, errors, diagnostics.len() - errors));
+    }
+    println!("{}: ok, {} warnings", filename, diagnostics.len());
+    Ok(())
+}
+
+fn export_nav_graphs(filename: &str, output_dir: &str) -> Result<(), String> {
+    let building = load(filename)?;
+    let written = nav_graph::write_nav_graphs(&building, Path::new(output_dir))
+        .map_err(|e| e.to_string())?;
+    for path in written {
+        println!("wrote nav graph [{}]", path.display());
+    }
+    Ok(())
+}
+
+fn export_world(filename: &str, output_dir: &str) -> Result<(), String> {
+    let building = load(filename)?;
+    let written = sdf::write_world(&building, Path::new(output_dir)).map_err(|e| e.to_string())?;
+    for path in written {
+        println!("wrote [{}]", path.display());
+    }
+    Ok(())
+}
+
+fn convert(input: &str, output: &str) -> Result<(), String> {
+    let building = load(input)?;
+    if output.ends_with(".glb") {
+        glb::write_glb(&building, Path::new(output), true).map_err(|e| e.to_string())?;
+        println!("wrote [{}]", output);
+        return Ok(());
+    }
+    let text = if output.ends_with(".yaml") || output.ends_with(".yml") {
+        building.to_yaml().map_err(|e| e.to_string())?
+    } else {
+        return Err(format!("cannot tell the format of [{}]; expected a .building.yaml or .glb file", output));
+    };
+    std::fs::write(output, text)
+        .map_err(|e| SiteMapError::Io { filename: output.to_string(), source: e }.to_string())?;
+    println!("wrote [{}]", output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFICE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/demo_maps/office.building.yaml");
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn subcommands_are_parsed() {
+        assert_eq!(parse(&[]), Ok(Command::Edit { filename: None }));
+        assert_eq!(parse(&["--help"]), Ok(Command::Help));
+        assert_eq!(parse(&["validate", "a.yaml"]), Ok(Command::Validate { filename: "a.yaml".into() }));
+        assert_eq!(
+            parse(&["export-navgraph", "a.yaml", "out"]),
+            Ok(Command::ExportNavGraph { filename: "a.yaml".into(), output_dir: "out".into() }));
+        assert_eq!(
+            parse(&["convert", "a.yaml", "b.glb"]),
+            Ok(Command::Convert { input: "a.yaml".into(), output: "b.glb".into() }));
+    }
+
+    #[test]
+    fn a_lone_filename_is_edited() {
+        let edit = Ok(Command::Edit { filename: Some("a.yaml".into()) });
+        assert_eq!(parse(&["a.yaml"]), edit);
+        assert_eq!(parse(&["edit", "a.yaml"]), edit);
+    }
+
+    #[test]
+    fn bad_arguments_are_refused() {
+        assert_eq!(parse(&["validate"]), Err("wrong arguments for [validate]".into()));
+        assert_eq!(parse(&["convert", "a.yaml"]), Err("wrong arguments for [convert]".into()));
+        assert_eq!(parse(&["--foo"]), Err("unknown command [--foo]".into()));
+        assert_eq!(parse(&["a.yaml", "b.yaml"]), Err("unknown command [a.yaml]".into()));
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(run_headless(&Command::Help), 0);
+        assert_eq!(run_headless(&Command::Edit { filename: None }), 2);
+
+        let output = std::env::temp_dir().join("traffic_editor_iii_cli_test.building.yaml");
+        let output = output.to_str().unwrap();
+        let convert = |input: &str, output: &str| run_headless(&Command::Convert {
+            input: input.into(),
+            output: output.into(),
+        });
+        assert_eq!(convert(OFFICE, output), 0);
+        assert!(BuildingMap::load(output).is_ok());
+        assert_eq!(convert(OFFICE, "office.txt"), 1);
+        assert_eq!(convert("no/such.building.yaml", output), 1);
+        std::fs::remove_file(output).unwrap();
+    }
+}
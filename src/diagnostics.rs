@@ -0,0 +1,85 @@
+use bevy::{
+    ecs::prelude::*,
+    prelude::{App, Plugin, Transform},
+};
+use bevy_egui::{egui, EguiContext};
+
+use crate::selection::Selection;
+use crate::site_map::SiteMap;
+use crate::supercamera::FlexibleProjection;
+use crate::validate::{validate, Diagnostic, Severity};
+
+/// The problems found in the map, kept up to date while the panel is open.
+#[derive(Default)]
+pub struct Diagnostics {
+    pub open: bool,
+    results: Vec<Diagnostic>,
+    /// The map revision `results` were computed for.
+    revision: Option<u64>,
+}
+
+/// Lists the problems in the map. Clicking one selects the element at
+/// fault and brings it into view.
+fn diagnostics_panel(
+    sm: Res<SiteMap>,
+    mut diagnostics: ResMut<Diagnostics>,
+    mut selection: ResMut<Selection>,
+    egui_context: Res<EguiContext>,
+    mut cameras: Query<(&mut Transform, &mut FlexibleProjection)>,
+) {
+    if !diagnostics.open {
+        return;
+    }
+    if diagnostics.revision != Some(sm.revision()) {
+        diagnostics.results = validate(&sm.to_building_map());
+        diagnostics.revision = Some(sm.revision());
+    }
+
+    let mut open = diagnostics.open;
+    let mut clicked = None;
+    egui::Window::new("Diagnostics")
+        .open(&mut open)
+        .show(egui_context.ctx(), |ui| {
+            let results = &diagnostics.results;
+            let errors = results.iter().filter(|d| d.severity == Severity::Error).count();
+            ui.label(format!("{} errors, {} warnings", errors, results.len() - errors));
+            ui.separator();
+            egui::ScrollArea::from_max_height(400.).show(ui, |ui| {
+                for (i, diagnostic) in results.iter().enumerate() {
+                    let icon = match diagnostic.severity {
+                        Severity::Error => "✖",
+                        Severity::Warning => "⚠",
+                    };
+                    let selected = diagnostic.element.is_some() && diagnostic.element == selection.selected;
+                    let text = format!("{} {}: {}", icon, diagnostic.level, diagnostic.message);
+                    if ui.selectable_label(selected, text).clicked() {
+                        clicked = Some(i);
+                    }
+                }
+            });
+        });
+    diagnostics.open = open;
+
+    let diagnostic = match clicked.and_then(|i| diagnostics.results.get(i)) {
+        Some(diagnostic) => diagnostic,
+        None => return,
+    };
+    if diagnostic.element.is_some() {
+        selection.selected = diagnostic.element.clone();
+    }
+    let xf = sm.level_transform(&diagnostic.level);
+    if let (Some(xf), Some((x, y))) = (xf, diagnostic.location) {
+        let (mut transform, mut projection) = cameras.single_mut();
+        projection.focus(&mut transform, xf.to_world(x, y).extend(xf.elevation));
+    }
+}
+
+#[derive(Default)]
+pub struct DiagnosticsPlugin;
+
+impl Plugin for DiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Diagnostics>()
+           .add_system(diagnostics_panel);
+    }
+}
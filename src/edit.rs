@@ -0,0 +1,487 @@
+// Reversible edits of a building's levels, and the undo history built from
+// them. Every change the editor makes to a map is expressed as an `Edit`, so
+// that it can be undone by applying its inverse.
+
+use std::collections::BTreeMap;
+
+use crate::building_map::{Door, Edge, ElementKind, Floor, Lane, Level, Vertex, Wall};
+
+/// Anything that can be added to or removed from one of a level's lists.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    Vertex(Vertex),
+    Lane(Lane),
+    Wall(Wall),
+    Door(Door),
+    Measurement(Edge),
+    Floor(Floor),
+    Hole(Floor),
+}
+
+impl Element {
+    /// A copy of the element at `index` in the list that `kind` names.
+    pub fn get(level: &Level, kind: ElementKind, index: usize) -> Option<Element> {
+        match kind {
+            ElementKind::Vertex => level.vertices.get(index).cloned().map(Element::Vertex),
+            ElementKind::Lane => level.lanes.get(index).cloned().map(Element::Lane),
+            ElementKind::Wall => level.walls.get(index).cloned().map(Element::Wall),
+            ElementKind::Door => level.doors.get(index).cloned().map(Element::Door),
+            ElementKind::Measurement => level.measurements.get(index).cloned().map(Element::Measurement),
+            ElementKind::Floor => level.floors.get(index).cloned().map(Element::Floor),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Edit {
+    /// Moves a vertex, in drawing coordinates.
+    MoveVertex {
+        level: String,
+        index: usize,
+        from: (f64, f64),
+        to: (f64, f64),
+    },
+    /// Inserts `element` into its list at `index`. Inserting a vertex shifts
+    /// every reference to the vertices after it.
+    Insert {
+        level: String,
+        index: usize,
+        element: Element,
+    },
+    /// Removes `element`, which must be what is stored at `index`. Removing a
+    /// vertex that is still referenced leaves dangling indices behind, so use
+    /// `Edit::delete_vertex` for that.
+    Remove {
+        level: String,
+        index: usize,
+        element: Element,
+    },
+    /// Swaps the element at `index` for another of the same kind, e.g. to
+    /// change its properties.
+    Replace {
+        level: String,
+        index: usize,
+        before: Element,
+        after: Element,
+    },
+    /// Several edits applied in order and undone together.
+    Batch(Vec<Edit>),
+}
+
+impl Edit {
+    /// The edit that takes a map back to where it was before this one.
+    pub fn inverse(&self) -> Edit {
+        match self {
+            Edit::MoveVertex { level, index, from, to } => Edit::MoveVertex {
+                level: level.clone(),
+                index: *index,
+                from: *to,
+                to: *from,
+            },
+            Edit::Insert { level, index, element } => Edit::Remove {
+                level: level.clone(),
+                index: *index,
+                element: element.clone(),
+            },
+            Edit::Remove { level, index, element } => Edit::Insert {
+                level: level.clone(),
+                index: *index,
+                element: element.clone(),
+            },
+            Edit::Replace { level, index, before, after } => Edit::Replace {
+                level: level.clone(),
+                index: *index,
+                before: after.clone(),
+                after: before.clone(),
+            },
+            Edit::Batch(edits) => Edit::Batch(edits.iter().rev().map(|e| e.inverse()).collect()),
+        }
+    }
+
+    /// Edits that no longer match the map (a missing level or an index out
+    /// of range) are skipped rather than panicking.
+    pub fn apply(&self, levels: &mut BTreeMap<String, Level>) {
+        match self {
+            Edit::MoveVertex { level, index, to, .. } => {
+                let v = levels.get_mut(level).and_then(|l| l.vertices.get_mut(*index));
+                if let Some(v) = v {
+                    v.x = to.0;
+                    v.y = to.1;
+                }
+            }
+            Edit::Insert { level, index, element } => {
+                if let Some(level) = levels.get_mut(level) {
+                    insert(level, *index, element.clone());
+                }
+            }
+            Edit::Remove { level, index, element } => {
+                if let Some(level) = levels.get_mut(level) {
+                    remove(level, *index, element);
+                }
+            }
+            Edit::Replace { level, index, after, .. } => {
+                if let Some(level) = levels.get_mut(level) {
+                    replace(level, *index, after.clone());
+                }
+            }
+            Edit::Batch(edits) => {
+                for edit in edits {
+                    edit.apply(levels);
+                }
+            }
+        }
+    }
+
+    /// Folds a following edit into this one, if the two form one continuous
+    /// gesture, such as successive steps of dragging the same vertex or of
+    /// editing the same element's properties.
+    fn absorb(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (
+                Edit::MoveVertex { level, index, to, .. },
+                Edit::MoveVertex { level: next_level, index: next_index, to: next_to, .. },
+            ) if level == next_level && index == next_index => {
+                *to = *next_to;
+                true
+            }
+            (
+                Edit::Replace { level, index, after, .. },
+                Edit::Replace { level: next_level, index: next_index, before: next_before, after: next_after },
+            ) if level == next_level
+                && index == next_index
+                && std::mem::discriminant(after) == std::mem::discriminant(next_before) =>
+            {
+                *after = next_after.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes a vertex together with the lanes, walls, doors and
+    /// measurements that use it, and takes it out of any floor or hole
+    /// outline. Outlines left with fewer than three corners are removed.
+    pub fn delete_vertex(level: &Level, index: usize) -> Edit {
+        let name = &level.name;
+        let mut edits = Vec::new();
+        let uses = |e: &Edge| e.start == index || e.end == index;
+
+        // remove from the back so that the earlier indices stay valid
+        let mut remove_edges = |edges: Vec<(usize, Element)>| {
+            for (i, element) in edges.into_iter().rev() {
+                edits.push(Edit::Remove { level: name.clone(), index: i, element });
+            }
+        };
+        remove_edges(level.lanes.iter().enumerate()
+            .filter(|(_, l)| l.start == index || l.end == index)
+            .map(|(i, e)| (i, Element::Lane(e.clone())))
+            .collect());
+        remove_edges(level.walls.iter().enumerate()
+            .filter(|(_, e)| uses(e))
+            .map(|(i, e)| (i, Element::Wall(e.clone())))
+            .collect());
+        remove_edges(level.doors.iter().enumerate()
+            .filter(|(_, d)| d.start == index || d.end == index)
+            .map(|(i, d)| (i, Element::Door(d.clone())))
+            .collect());
+        remove_edges(level.measurements.iter().enumerate()
+            .filter(|(_, e)| uses(e))
+            .map(|(i, e)| (i, Element::Measurement(e.clone())))
+            .collect());
+
+        let outlines = [
+            (&level.floors, Element::Floor as fn(Floor) -> Element),
+            (&level.holes, Element::Hole as fn(Floor) -> Element),
+        ];
+        for (outlines, wrap) in outlines.iter() {
+            for (i, outline) in outlines.iter().enumerate().rev() {
+                if !outline.vertices.contains(&index) {
+                    continue;
+                }
+                edits.push(Edit::Remove { level: name.clone(), index: i, element: wrap(outline.clone()) });
+                let mut trimmed = outline.clone();
+                trimmed.vertices.retain(|&v| v != index);
+                if trimmed.vertices.len() >= 3 {
+                    edits.push(Edit::Insert { level: name.clone(), index: i, element: wrap(trimmed) });
+                }
+            }
+        }
+
+        if let Some(v) = level.vertices.get(index) {
+            edits.push(Edit::Remove {
+                level: name.clone(),
+                index,
+                element: Element::Vertex(v.clone()),
+            });
+        }
+        Edit::Batch(edits)
+    }
+}
+
+fn insert(level: &mut Level, index: usize, element: Element) {
+    fn insert_at<T>(list: &mut Vec<T>, index: usize, item: T) {
+        if index <= list.len() {
+            list.insert(index, item);
+        }
+    }
+    match element {
+        Element::Vertex(v) => {
+            if index <= level.vertices.len() {
+                shift_vertex_references(level, |i| if i >= index { i + 1 } else { i });
+                level.vertices.insert(index, v);
+            }
+        }
+        Element::Lane(lane) => insert_at(&mut level.lanes, index, lane),
+        Element::Wall(wall) => insert_at(&mut level.walls, index, wall),
+        Element::Door(door) => insert_at(&mut level.doors, index, door),
+        Element::Measurement(m) => insert_at(&mut level.measurements, index, m),
+        Element::Floor(floor) => insert_at(&mut level.floors, index, floor),
+        Element::Hole(hole) => insert_at(&mut level.holes, index, hole),
+    }
+}
+
+fn remove(level: &mut Level, index: usize, element: &Element) {
+    fn remove_at<T>(list: &mut Vec<T>, index: usize) {
+        if index < list.len() {
+            list.remove(index);
+        }
+    }
+    match element {
+        Element::Vertex(_) => {
+            if index < level.vertices.len() {
+                level.vertices.remove(index);
+                shift_vertex_references(level, |i| if i > index { i - 1 } else { i });
+            }
+        }
+        Element::Lane(_) => remove_at(&mut level.lanes, index),
+        Element::Wall(_) => remove_at(&mut level.walls, index),
+        Element::Door(_) => remove_at(&mut level.doors, index),
+        Element::Measurement(_) => remove_at(&mut level.measurements, index),
+        Element::Floor(_) => remove_at(&mut level.floors, index),
+        Element::Hole(_) => remove_at(&mut level.holes, index),
+    }
+}
+
+fn replace(level: &mut Level, index: usize, element: Element) {
+    fn replace_at<T>(list: &mut [T], index: usize, item: T) {
+        if let Some(slot) = list.get_mut(index) {
+            *slot = item;
+        }
+    }
+    match element {
+        Element::Vertex(v) => replace_at(&mut level.vertices, index, v),
+        Element::Lane(lane) => replace_at(&mut level.lanes, index, lane),
+        Element::Wall(wall) => replace_at(&mut level.walls, index, wall),
+        Element::Door(door) => replace_at(&mut level.doors, index, door),
+        Element::Measurement(m) => replace_at(&mut level.measurements, index, m),
+        Element::Floor(floor) => replace_at(&mut level.floors, index, floor),
+        Element::Hole(hole) => replace_at(&mut level.holes, index, hole),
+    }
+}
+
+fn shift_vertex_references(level: &mut Level, shift: impl Fn(usize) -> usize) {
+    for edge in level.walls.iter_mut().chain(level.measurements.iter_mut()) {
+        edge.start = shift(edge.start);
+        edge.end = shift(edge.end);
+    }
+    for lane in level.lanes.iter_mut() {
+        lane.start = shift(lane.start);
+        lane.end = shift(lane.end);
+    }
+    for door in level.doors.iter_mut() {
+        door.start = shift(door.start);
+        door.end = shift(door.end);
+    }
+    for outline in level.floors.iter_mut().chain(level.holes.iter_mut()) {
+        for v in outline.vertices.iter_mut() {
+            *v = shift(*v);
+        }
+    }
+}
+
+/// Everything done to a map since it was loaded, for undo and redo.
+#[derive(Default)]
+pub struct EditHistory {
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+    /// Set after `push_coalesced`, until something else is recorded.
+    coalescing: bool,
+}
+
+impl EditHistory {
+    pub fn push(&mut self, edit: Edit) {
+        self.undo.push(edit);
+        self.redo.clear();
+        self.coalescing = false;
+    }
+
+    /// Records one step of a continuous gesture, merging it into the
+    /// previous step of the same gesture where possible.
+    pub fn push_coalesced(&mut self, edit: Edit) {
+        let merged = match self.undo.last_mut() {
+            Some(last) if self.coalescing => last.absorb(&edit),
+            _ => false,
+        };
+        if !merged {
+            self.undo.push(edit);
+        }
+        self.redo.clear();
+        self.coalescing = true;
+    }
+
+    /// Ends the current gesture, so that the next step starts a new entry.
+    /// Returns whether there was a gesture to end.
+    pub fn end_coalescing(&mut self) -> bool {
+        std::mem::replace(&mut self.coalescing, false)
+    }
+
+    /// The edit that undoes the most recent entry, moving it to the redo list.
+    pub fn undo(&mut self) -> Option<Edit> {
+        self.coalescing = false;
+        let edit = self.undo.pop()?;
+        let inverse = edit.inverse();
+        self.redo.push(edit);
+        Some(inverse)
+    }
+
+    pub fn redo(&mut self) -> Option<Edit> {
+        self.coalescing = false;
+        let edit = self.redo.pop()?;
+        self.undo.push(edit.clone());
+        Some(edit)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = EditHistory::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A square of four vertices, with a lane and a wall along each side
+    /// and a floor over it all.
+    fn square() -> BTreeMap<String, Level> {
+        let mut level = Level { name: "L1".into(), ..Default::default() };
+        for (x, y) in [(0., 0.), (10., 0.), (10., 10.), (0., 10.)] {
+            level.vertices.push(Vertex { x, y, ..Default::default() });
+        }
+        for i in 0..4 {
+            level.lanes.push(Lane::new(i, (i + 1) % 4));
+            level.walls.push(Edge { start: i, end: (i + 1) % 4, ..Default::default() });
+        }
+        level.floors.push(Floor { vertices: vec![0, 1, 2, 3], ..Default::default() });
+        let mut levels = BTreeMap::new();
+        levels.insert("L1".to_string(), level);
+        levels
+    }
+
+    fn move_vertex(index: usize, from: (f64, f64), to: (f64, f64)) -> Edit {
+        Edit::MoveVertex { level: "L1".into(), index, from, to }
+    }
+
+    fn position(levels: &BTreeMap<String, Level>, index: usize) -> (f64, f64) {
+        let v = &levels["L1"].vertices[index];
+        (v.x, v.y)
+    }
+
+    #[test]
+    fn undo_and_redo_go_in_order() {
+        let mut levels = square();
+        let mut history = EditHistory::default();
+        for edit in [move_vertex(0, (0., 0.), (1., 1.)), move_vertex(0, (1., 1.), (2., 2.))] {
+            edit.apply(&mut levels);
+            history.push(edit);
+        }
+
+        history.undo().unwrap().apply(&mut levels);
+        assert_eq!(position(&levels, 0), (1., 1.));
+        history.undo().unwrap().apply(&mut levels);
+        assert_eq!(position(&levels, 0), (0., 0.));
+        assert!(history.undo().is_none());
+
+        history.redo().unwrap().apply(&mut levels);
+        assert_eq!(position(&levels, 0), (1., 1.));
+        history.redo().unwrap().apply(&mut levels);
+        assert_eq!(position(&levels, 0), (2., 2.));
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut levels = square();
+        let mut history = EditHistory::default();
+        let edit = move_vertex(1, (10., 0.), (11., 0.));
+        edit.apply(&mut levels);
+        history.push(edit);
+        history.undo().unwrap().apply(&mut levels);
+        assert!(history.can_redo());
+
+        let edit = move_vertex(2, (10., 10.), (12., 12.));
+        edit.apply(&mut levels);
+        history.push(edit);
+        assert!(!history.can_redo());
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn a_drag_is_undone_in_one_step() {
+        let mut levels = square();
+        let mut history = EditHistory::default();
+        let mut from = (0., 0.);
+        for step in 1..=5 {
+            let to = (step as f64, 0.);
+            let edit = move_vertex(0, from, to);
+            edit.apply(&mut levels);
+            history.push_coalesced(edit);
+            from = to;
+        }
+        assert!(history.end_coalescing());
+
+        // the next drag of the same vertex is a step of its own
+        let edit = move_vertex(0, from, (6., 0.));
+        edit.apply(&mut levels);
+        history.push_coalesced(edit);
+        history.end_coalescing();
+
+        history.undo().unwrap().apply(&mut levels);
+        assert_eq!(position(&levels, 0), (5., 0.));
+        history.undo().unwrap().apply(&mut levels);
+        assert_eq!(position(&levels, 0), (0., 0.));
+        assert!(!history.can_undo());
+    }
+
+    #[test]
+    fn deleting_a_vertex_takes_what_is_attached_with_it() {
+        let mut levels = square();
+        let edit = Edit::delete_vertex(&levels["L1"], 1);
+        edit.apply(&mut levels);
+
+        let level = &levels["L1"];
+        assert_eq!(level.vertices.len(), 3);
+        // the sides 0-1 and 1-2 are gone, and the others now count from
+        // the vertices that are left
+        let lanes: Vec<(usize, usize)> = level.lanes.iter().map(|l| (l.start, l.end)).collect();
+        let walls: Vec<(usize, usize)> = level.walls.iter().map(|w| (w.start, w.end)).collect();
+        assert_eq!(lanes, vec![(1, 2), (2, 0)]);
+        assert_eq!(walls, vec![(1, 2), (2, 0)]);
+        assert_eq!(level.floors[0].vertices, vec![0, 1, 2]);
+
+        edit.inverse().apply(&mut levels);
+        let restored = &levels["L1"];
+        let original = &square()["L1"];
+        assert_eq!(restored.vertices, original.vertices);
+        assert_eq!(restored.lanes, original.lanes);
+        assert_eq!(restored.walls, original.walls);
+        assert_eq!(restored.floors, original.floors);
+    }
+}
@@ -0,0 +1,66 @@
+use bevy::{
+    ecs::prelude::*,
+    prelude::{App, Plugin, Transform},
+    window::Windows,
+};
+
+use crate::selection::Selection;
+use crate::site_map::SiteMap;
+use crate::supercamera::{FlexibleProjection, SUPERCAMERA_MOTION};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameTarget {
+    /// Every level of the site.
+    All,
+    /// The selected element, or the whole site if nothing is selected.
+    Selection,
+}
+
+/// Set to have the camera fit something in view on the next frame.
+#[derive(Default)]
+pub struct FrameRequest(pub Option<FrameTarget>);
+
+/// Fits the view to whatever was asked for, and to the whole site after
+/// every load.
+fn frame_camera(
+    sm: Res<SiteMap>,
+    selection: Res<Selection>,
+    windows: Res<Windows>,
+    mut request: ResMut<FrameRequest>,
+    mut loads: Local<u64>,
+    mut query: Query<(&mut Transform, &mut FlexibleProjection)>,
+) {
+    if *loads != sm.loads() {
+        *loads = sm.loads();
+        request.0 = Some(FrameTarget::All);
+    }
+    let target = match request.0.take() {
+        Some(target) => target,
+        None => return,
+    };
+    let selected = match (target, &selection.selected) {
+        (FrameTarget::Selection, Some(id)) => sm.element_bounds(id),
+        _ => None,
+    };
+    let (min, max) = match selected.or_else(|| sm.bounds()) {
+        Some(bounds) => bounds,
+        None => return,
+    };
+    let aspect_ratio = match windows.get_primary() {
+        Some(window) if window.height() > 0. => window.width() / window.height(),
+        _ => 1.,
+    };
+    let (mut transform, mut projection) = query.single_mut();
+    projection.frame(&mut transform, min, max, aspect_ratio);
+}
+
+#[derive(Default)]
+pub struct FramingPlugin;
+
+impl Plugin for FramingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameRequest>()
+           // after the camera has handled any switch of projection mode
+           .add_system(frame_camera.after(SUPERCAMERA_MOTION));
+    }
+}
@@ -0,0 +1,276 @@
+// The 3D shapes of a building's walls, floors and doors, independent of the
+// renderer, so that they can be exported without a window. Coordinates are
+// in metres, in the frame the RMF tools use: x along the drawing, y up it,
+// and z up from the level's elevation.
+
+use crate::building_map::{Door, Lane, Level, MotionAxis};
+use crate::triangulate;
+
+pub const WALL_THICKNESS: f64 = 0.1;
+pub const WALL_HEIGHT: f64 = 1.0;
+pub const DOOR_THICKNESS: f64 = 0.05;
+pub const DOOR_HEIGHT: f64 = 0.95;
+pub const LANE_WIDTH: f64 = 0.5;
+
+/// Where a vertex of the level is, in metres. None for a dangling index.
+pub fn metres(level: &Level, vertex: usize) -> Option<[f64; 2]> {
+    let scale = level.scale();
+    level.vertices.get(vertex).map(|v| [v.x * scale, -v.y * scale])
+}
+
+/// An indexed triangle mesh.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    /// Counter-clockwise triangles.
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn append(&mut self, other: &MeshData) {
+        let offset = self.positions.len() as u32;
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.uvs.extend_from_slice(&other.uvs);
+        self.indices.extend(other.indices.iter().map(|i| i + offset));
+    }
+
+    /// A box of the given size, centred on `center` and turned by `yaw`
+    /// about the z axis.
+    pub fn cuboid(center: [f64; 3], yaw: f64, size: [f64; 3]) -> MeshData {
+        let (sin, cos) = yaw.sin_cos();
+        let half = [size[0] / 2., size[1] / 2., size[2] / 2.];
+        let place = |p: [f64; 3]| -> [f32; 3] {
+            [
+                (center[0] + p[0] * cos - p[1] * sin) as f32,
+                (center[1] + p[0] * sin + p[1] * cos) as f32,
+                (center[2] + p[2]) as f32,
+            ]
+        };
+        let turn = |n: [f64; 3]| -> [f32; 3] {
+            [(n[0] * cos - n[1] * sin) as f32, (n[0] * sin + n[1] * cos) as f32, n[2] as f32]
+        };
+
+        let mut mesh = MeshData::default();
+        // each face as its normal axis and the two axes spanning it, chosen
+        // so that u × v points along the normal
+        let faces = [
+            (0, 1., 1, 2), (0, -1., 2, 1),
+            (1, 1., 2, 0), (1, -1., 0, 2),
+            (2, 1., 0, 1), (2, -1., 1, 0),
+        ];
+        for (axis, sign, u, v) in faces {
+            let first = mesh.positions.len() as u32;
+            let mut normal = [0.; 3];
+            normal[axis] = sign;
+            for (su, sv) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+                let mut p = [0.; 3];
+                p[axis] = sign * half[axis];
+                p[u] = su * half[u];
+                p[v] = sv * half[v];
+                mesh.positions.push(place(p));
+                mesh.normals.push(turn(normal));
+                mesh.uvs.push([(su + 1.) as f32 / 2., (sv + 1.) as f32 / 2.]);
+            }
+            mesh.indices.extend([first, first + 1, first + 2, first, first + 2, first + 3]);
+        }
+        mesh
+    }
+
+    /// A flat rectangle facing up, centred on `center` and turned by `yaw`
+    /// about the z axis.
+    pub fn rectangle(center: [f64; 3], yaw: f64, size: [f64; 2]) -> MeshData {
+        let (sin, cos) = yaw.sin_cos();
+        let mut mesh = MeshData::default();
+        for (su, sv) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)] {
+            let (u, v) = (su * size[0] / 2., sv * size[1] / 2.);
+            mesh.positions.push([
+                (center[0] + u * cos - v * sin) as f32,
+                (center[1] + u * sin + v * cos) as f32,
+                center[2] as f32,
+            ]);
+            mesh.normals.push([0., 0., 1.]);
+            mesh.uvs.push([(su + 1.) as f32 / 2., (sv + 1.) as f32 / 2.]);
+        }
+        mesh.indices.extend([0, 1, 2, 0, 2, 3]);
+        mesh
+    }
+
+    /// Writes the mesh as a Wavefront OBJ object named `name`.
+    pub fn to_obj(&self, name: &str) -> String {
+        let mut obj = format!("o {}\n", name);
+        for p in &self.positions {
+            obj += &format!("v {} {} {}\n", p[0], p[1], p[2]);
+        }
+        for t in &self.uvs {
+            obj += &format!("vt {} {}\n", t[0], t[1]);
+        }
+        for n in &self.normals {
+            obj += &format!("vn {} {} {}\n", n[0], n[1], n[2]);
+        }
+        for t in self.indices.chunks(3) {
+            // OBJ indices start at 1
+            let (a, b, c) = (t[0] + 1, t[1] + 1, t[2] + 1);
+            obj += &format!("f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}\n", a, b, c);
+        }
+        obj
+    }
+}
+
+/// All the walls of a level as one mesh, standing on z = 0.
+pub fn wall_mesh(level: &Level) -> MeshData {
+    let mut mesh = MeshData::default();
+    for (_, wall) in wall_meshes(level) {
+        mesh.append(&wall);
+    }
+    mesh
+}
+
+/// Each wall of a level with its index, standing on z = 0. Walls with
+/// dangling vertices or no length are left out.
+pub fn wall_meshes(level: &Level) -> Vec<(usize, MeshData)> {
+    let mut meshes = Vec::new();
+    for (i, wall) in level.walls.iter().enumerate() {
+        let (a, b) = match (metres(level, wall.start), metres(level, wall.end)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+        let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length < 1e-6 {
+            continue;
+        }
+        meshes.push((i, MeshData::cuboid(
+            [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2., WALL_HEIGHT / 2.],
+            dy.atan2(dx),
+            [length, WALL_THICKNESS, WALL_HEIGHT],
+        )));
+    }
+    meshes
+}
+
+/// A lane as a strip `LANE_WIDTH` wide lying just above z = 0. None for
+/// dangling vertices or lanes of no length.
+pub fn lane_mesh(level: &Level, lane: &Lane) -> Option<MeshData> {
+    let (a, b) = (metres(level, lane.start)?, metres(level, lane.end)?);
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    let length = (dx * dx + dy * dy).sqrt();
+    if length < 1e-6 {
+        return None;
+    }
+    Some(MeshData::rectangle(
+        [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2., 0.01],
+        dy.atan2(dx),
+        [length, LANE_WIDTH],
+    ))
+}
+
+/// Each floor of a level with its holes cut out, at z = 0, together with
+/// the floor's texture name. Texture coordinates tile every
+/// `texture_scale` metres.
+pub fn floor_meshes(level: &Level) -> Vec<(MeshData, String)> {
+    let polygon = |indices: &[usize]| -> Vec<[f64; 2]> {
+        indices.iter().filter_map(|&i| metres(level, i)).collect()
+    };
+    let holes: Vec<Vec<[f64; 2]>> = level.holes.iter().map(|h| polygon(&h.vertices)).collect();
+
+    let mut meshes = Vec::new();
+    for floor in &level.floors {
+        let outline = polygon(&floor.vertices);
+        let floor_holes: Vec<Vec<[f64; 2]>> = holes.iter()
+            .filter(|h| !h.is_empty() && triangulate::contains(&outline, h[0]))
+            .cloned()
+            .collect();
+        let triangles = triangulate::triangulate(&outline, &floor_holes);
+        if triangles.is_empty() {
+            continue;
+        }
+        let rotation = floor.texture_rotation().to_radians();
+        let (sin, cos) = (rotation.sin(), rotation.cos());
+        let tile = floor.texture_scale().max(1e-3);
+        let points: Vec<[f64; 2]> = outline.iter().chain(floor_holes.iter().flatten()).cloned().collect();
+        let mesh = MeshData {
+            positions: points.iter().map(|p| [p[0] as f32, p[1] as f32, 0.]).collect(),
+            normals: vec![[0., 0., 1.]; points.len()],
+            uvs: points.iter().map(|p| {
+                [
+                    ((p[0] * cos + p[1] * sin) / tile) as f32,
+                    ((-p[0] * sin + p[1] * cos) / tile) as f32,
+                ]
+            }).collect(),
+            indices: triangles.iter().flat_map(|t| t.iter().map(|&i| i as u32)).collect(),
+        };
+        meshes.push((mesh, floor.texture_name().to_string()));
+    }
+    meshes
+}
+
+/// One leaf of a door in its closed position.
+pub struct DoorPanel {
+    /// The hinge, or for sliding doors the end the panel slides past.
+    pub pivot: [f64; 2],
+    /// Unit vector from the pivot along the closed panel.
+    pub dir: [f64; 2],
+    pub length: f64,
+    /// Signed swing angle in radians; zero for sliding panels.
+    pub swing: f64,
+}
+
+/// The leaves of a door spanning `start` to `end`: one, or two for double
+/// doors.
+pub fn door_panels(door: &Door, start: [f64; 2], end: [f64; 2]) -> Vec<DoorPanel> {
+    let span = [end[0] - start[0], end[1] - start[1]];
+    let length = (span[0] * span[0] + span[1] * span[1]).sqrt();
+    if length < 1e-6 {
+        return Vec::new();
+    }
+    let dir = [span[0] / length, span[1] / length];
+    let back = [-dir[0], -dir[1]];
+    let swing = if door.door_type.is_sliding() {
+        0.
+    } else {
+        door.motion_degrees.to_radians() * door.motion_direction.signum() as f64
+    };
+
+    if door.door_type.is_double() {
+        let ratio = door.right_left_ratio.max(0.);
+        let left = length / (1. + ratio);
+        vec![
+            DoorPanel { pivot: start, dir, length: left, swing },
+            DoorPanel { pivot: end, dir: back, length: length - left, swing: -swing },
+        ]
+    } else {
+        match door.motion_axis {
+            MotionAxis::Start => vec![DoorPanel { pivot: start, dir, length, swing }],
+            MotionAxis::End => vec![DoorPanel { pivot: end, dir: back, length, swing: -swing }],
+        }
+    }
+}
+
+/// The leaves of a door as one mesh, closed and standing on z = 0. Empty
+/// for dangling vertices.
+pub fn door_mesh(level: &Level, door: &Door) -> MeshData {
+    let mut mesh = MeshData::default();
+    let (start, end) = match (metres(level, door.start), metres(level, door.end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return mesh,
+    };
+    for panel in door_panels(door, start, end) {
+        mesh.append(&MeshData::cuboid(
+            [
+                panel.pivot[0] + panel.dir[0] * panel.length / 2.,
+                panel.pivot[1] + panel.dir[1] * panel.length / 2.,
+                DOOR_HEIGHT / 2.,
+            ],
+            panel.dir[1].atan2(panel.dir[0]),
+            [panel.length, DOOR_THICKNESS, DOOR_HEIGHT],
+        ));
+    }
+    mesh
+}
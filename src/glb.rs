@@ -0,0 +1,316 @@
+// Writes a building as a single binary glTF (.glb) file for viewers that
+// only need to look at the site, such as web dashboards. Each level is a
+// node at its elevation, holding one node per wall, floor, door and
+// optionally lane, named after the element. Doors are shown closed.
+//
+// glTF is y-up, so the RMF frame (x, y, z) is written as (x, z, -y).
+// Floors keep their tiling texture coordinates but not their textures.
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::building_map::{BuildingMap, SiteMapError};
+use crate::geometry::{door_mesh, floor_meshes, lane_mesh, wall_meshes, MeshData};
+
+// the colours SiteHandles gives each kind of element in the editor
+const WALL_COLOR: [f32; 4] = [0.5, 0.5, 1.0, 1.0];
+const DOOR_COLOR: [f32; 4] = [0.6, 0.4, 0.2, 1.0];
+const FLOOR_COLOR: [f32; 4] = [0.3, 0.3, 0.3, 1.0];
+const LANE_COLORS: [[f32; 4]; 6] = [
+    [1.0, 0.5, 0.3, 0.5],
+    [0.3, 0.8, 0.3, 0.5],
+    [0.3, 0.5, 1.0, 0.5],
+    [0.9, 0.3, 0.8, 0.5],
+    [0.9, 0.9, 0.2, 0.5],
+    [0.2, 0.9, 0.9, 0.5],
+];
+
+// indices into the materials written by Glb::new()
+const WALL_MATERIAL: usize = 0;
+const DOOR_MATERIAL: usize = 1;
+const FLOOR_MATERIAL: usize = 2;
+const FIRST_LANE_MATERIAL: usize = 3;
+
+// glTF enums
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/// Writes the building to `path` as a .glb, with lanes as flat strips
+/// above the floors if `lanes` is set.
+pub fn write_glb(building: &BuildingMap, path: &Path, lanes: bool) -> Result<(), SiteMapError> {
+    fs::write(path, to_glb(building, lanes))
+        .map_err(|source| SiteMapError::Io { filename: path.display().to_string(), source })
+}
+
+/// The building as the bytes of a .glb file.
+pub fn to_glb(building: &BuildingMap, lanes: bool) -> Vec<u8> {
+    let mut glb = Glb::new();
+    let mut roots = Vec::new();
+    for level in building.levels.values() {
+        let mut children = Vec::new();
+        for (i, (mesh, _texture)) in floor_meshes(level).iter().enumerate() {
+            children.push(glb.node(&format!("floor_{}", i), mesh, FLOOR_MATERIAL));
+        }
+        for (i, mesh) in wall_meshes(level) {
+            children.push(glb.node(&format!("wall_{}", i), &mesh, WALL_MATERIAL));
+        }
+        for (i, door) in level.doors.iter().enumerate() {
+            let mesh = door_mesh(level, door);
+            if mesh.is_empty() {
+                continue;
+            }
+            let name = if door.name.is_empty() { format!("door_{}", i) } else { door.name.clone() };
+            children.push(glb.node(&name, &mesh, DOOR_MATERIAL));
+        }
+        if lanes {
+            for (i, lane) in level.lanes.iter().enumerate() {
+                if let Some(mesh) = lane_mesh(level, lane) {
+                    let material = FIRST_LANE_MATERIAL
+                        + lane.graph_idx.rem_euclid(LANE_COLORS.len() as i64) as usize;
+                    children.push(glb.node(&format!("lane_{}", i), &mesh, material));
+                }
+            }
+        }
+        glb.nodes.push(json!({
+            "name": level.name,
+            "translation": [0., level.elevation, 0.],
+            "children": children,
+        }));
+        roots.push(glb.nodes.len() - 1);
+    }
+    glb.finish(roots)
+}
+
+/// The parts of a glTF document that grow as meshes are added, and the
+/// binary buffer they point into.
+struct Glb {
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+    materials: Vec<Value>,
+}
+
+impl Glb {
+    fn new() -> Glb {
+        let material = |name: &str, color: [f32; 4]| -> Value {
+            let mut material = json!({
+                "name": name,
+                "pbrMetallicRoughness": {
+                    "baseColorFactor": color,
+                    "metallicFactor": 0.0,
+                    "roughnessFactor": 0.9,
+                },
+            });
+            if color[3] < 1. {
+                material["alphaMode"] = json!("BLEND");
+                material["doubleSided"] = json!(true);
+            }
+            material
+        };
+        let mut materials = vec![
+            material("wall", WALL_COLOR),
+            material("door", DOOR_COLOR),
+            material("floor", FLOOR_COLOR),
+        ];
+        for (i, color) in LANE_COLORS.iter().enumerate() {
+            materials.push(material(&format!("lane_graph_{}", i), *color));
+        }
+        Glb {
+            buffer: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            meshes: Vec::new(),
+            nodes: Vec::new(),
+            materials,
+        }
+    }
+
+    /// Adds `mesh` and a node named `name` showing it, and returns the
+    /// node's index.
+    fn node(&mut self, name: &str, mesh: &MeshData, material: usize) -> usize {
+        let y_up = |p: &[f32; 3]| [p[0], p[2], -p[1]];
+        let positions: Vec<[f32; 3]> = mesh.positions.iter().map(y_up).collect();
+        let normals: Vec<[f32; 3]> = mesh.normals.iter().map(y_up).collect();
+
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for p in &positions {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        let position = self.accessor(
+            positions.iter().flatten().map(|f| f.to_le_bytes()),
+            json!({ "componentType": FLOAT, "count": positions.len(), "type": "VEC3", "min": min, "max": max }),
+            ARRAY_BUFFER);
+        let normal = self.accessor(
+            normals.iter().flatten().map(|f| f.to_le_bytes()),
+            json!({ "componentType": FLOAT, "count": normals.len(), "type": "VEC3" }),
+            ARRAY_BUFFER);
+        let uv = self.accessor(
+            mesh.uvs.iter().flatten().map(|f| f.to_le_bytes()),
+            json!({ "componentType": FLOAT, "count": mesh.uvs.len(), "type": "VEC2" }),
+            ARRAY_BUFFER);
+        let indices = self.accessor(
+            mesh.indices.iter().map(|i| i.to_le_bytes()),
+            json!({ "componentType": UNSIGNED_INT, "count": mesh.indices.len(), "type": "SCALAR" }),
+            ELEMENT_ARRAY_BUFFER);
+
+        self.meshes.push(json!({
+            "name": name,
+            "primitives": [{
+                "attributes": { "POSITION": position, "NORMAL": normal, "TEXCOORD_0": uv },
+                "indices": indices,
+                "material": material,
+            }],
+        }));
+        self.nodes.push(json!({ "name": name, "mesh": self.meshes.len() - 1 }));
+        self.nodes.len() - 1
+    }
+
+    /// Appends four-byte values to the buffer, in a view of their own, and
+    /// returns the index of an accessor to them.
+    fn accessor(&mut self, values: impl Iterator<Item = [u8; 4]>, mut accessor: Value, target: u32) -> usize {
+        let offset = self.buffer.len();
+        self.buffer.extend(values.flatten());
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.buffer.len() - offset,
+            "target": target,
+        }));
+        accessor["bufferView"] = json!(self.buffer_views.len() - 1);
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    /// The .glb file: a header, then the JSON document and the buffer as
+    /// chunks padded to four bytes.
+    fn finish(self, roots: Vec<usize>) -> Vec<u8> {
+        let mut document = json!({
+            "asset": { "version": "2.0", "generator": "traffic_editor_iii" },
+            "scene": 0,
+            "scenes": [{ "nodes": roots }],
+            "nodes": self.nodes,
+            "materials": self.materials,
+        });
+        if !self.meshes.is_empty() {
+            document["meshes"] = json!(self.meshes);
+            document["accessors"] = json!(self.accessors);
+            document["bufferViews"] = json!(self.buffer_views);
+            document["buffers"] = json!([{ "byteLength": self.buffer.len() }]);
+        }
+        let mut text = document.to_string().into_bytes();
+        text.resize(text.len() + (4 - text.len() % 4) % 4, b' ');
+        let mut chunks = chunk(b"JSON", &text);
+        if !self.buffer.is_empty() {
+            chunks.extend(chunk(b"BIN\0", &self.buffer));
+        }
+
+        let mut glb = Vec::with_capacity(12 + chunks.len());
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(12 + chunks.len() as u32).to_le_bytes());
+        glb.extend(chunks);
+        glb
+    }
+}
+
+fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(8 + data.len());
+    chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    const BUILDING: &str = "\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices: [[0, 0, 0, ''], [400, 0, 0, ''], [400, 400, 0, ''], [0, 400, 0, ''], [100, 200, 0, ''], [300, 200, 0, '']]
+    walls: [[0, 1, {}], [1, 2, {}]]
+    doors: [[2, 3, {name: [1, door1], type: [1, hinged]}]]
+    floors: [{vertices: [0, 1, 2, 3]}]
+    lanes: [[4, 5, {bidirectional: [4, true], graph_idx: [2, 0]}]]
+  L2:
+    elevation: 3
+    vertices: [[0, 0, 0, ''], [400, 0, 0, '']]
+    walls: [[0, 1, {}]]
+";
+
+    fn u32_at(bytes: &[u8], offset: usize) -> usize {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+    }
+
+    /// Checks the framing of a .glb and returns its JSON document.
+    fn document(glb: &[u8]) -> Value {
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(u32_at(glb, 4), 2);
+        assert_eq!(u32_at(glb, 8), glb.len());
+
+        let json_length = u32_at(glb, 12);
+        assert_eq!(&glb[16..20], b"JSON");
+        assert_eq!(json_length % 4, 0);
+        let document: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+
+        let bin = 20 + json_length;
+        let bin_length = u32_at(glb, bin);
+        assert_eq!(&glb[bin + 4..bin + 8], b"BIN\0");
+        assert_eq!(bin + 8 + bin_length, glb.len());
+        assert_eq!(document["buffers"][0]["byteLength"], bin_length);
+        for view in document["bufferViews"].as_array().unwrap() {
+            let end = view["byteOffset"].as_u64().unwrap() + view["byteLength"].as_u64().unwrap();
+            assert!(end as usize <= bin_length);
+        }
+        document
+    }
+
+    /// Each level node's name, elevation and the names of its children.
+    fn levels(document: &Value) -> Vec<(String, f64, Vec<String>)> {
+        let nodes = document["nodes"].as_array().unwrap();
+        let name = |node: &Value| node["name"].as_str().unwrap().to_string();
+        document["scenes"][0]["nodes"].as_array().unwrap().iter()
+            .map(|root| &nodes[root.as_u64().unwrap() as usize])
+            .map(|level| (
+                name(level),
+                level["translation"][1].as_f64().unwrap(),
+                level["children"].as_array().unwrap().iter()
+                    .map(|child| name(&nodes[child.as_u64().unwrap() as usize]))
+                    .collect(),
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn one_node_per_level_with_named_elements() {
+        let building = BuildingMap::from_yaml(BUILDING).unwrap();
+        let document = document(&to_glb(&building, true));
+        let levels = levels(&document);
+        assert_eq!(levels.len(), 2);
+        assert_eq!((levels[0].0.as_str(), levels[0].1), ("L1", 0.));
+        assert_eq!(levels[0].2, vec!["floor_0", "wall_0", "wall_1", "door1", "lane_0"]);
+        assert_eq!((levels[1].0.as_str(), levels[1].1), ("L2", 3.));
+        assert_eq!(levels[1].2, vec!["wall_0"]);
+    }
+
+    #[test]
+    fn lanes_are_optional() {
+        let building = BuildingMap::from_yaml(BUILDING).unwrap();
+        let document = document(&to_glb(&building, false));
+        assert_eq!(levels(&document)[0].2, vec!["floor_0", "wall_0", "wall_1", "door1"]);
+    }
+}
@@ -9,16 +9,13 @@ use bevy::{
         DirectionalLight,
         DirectionalLightBundle,
         DirectionalLightShadowMap,
-        PbrBundle,
         // PointLightBundle,
         // PointLight,
-        StandardMaterial,
     },
-    prelude::{App, Assets, AssetServer, Transform},
+    prelude::{App, Transform},
     PipelinedDefaultPlugins,
     render2::{
         color::Color,
-        mesh::{shape, Mesh},
         //view::Msaa
     },
     window::{WindowDescriptor},
@@ -43,32 +40,93 @@ use bevy_egui::{egui, EguiContext, EguiPlugin};
 mod supercamera;
 use supercamera::{SuperCameraPlugin, FlexibleProjection, ProjectionMode};
 
+mod building_map;
+mod cli;
+mod edit;
+mod geometry;
+mod glb;
+mod nav_graph;
+mod sdf;
+mod triangulate;
+mod validate;
+
 mod site_map;
-use site_map::{SiteMap, SiteMapPlugin};
+use site_map::{SiteMap, SiteMapPlugin, SiteMapStatus};
+
+mod selection;
+use selection::SelectionPlugin;
+
+mod tools;
+use tools::{EditorTools, ToolsPlugin};
+
+mod properties;
+use properties::PropertiesPlugin;
+
+mod diagnostics;
+use diagnostics::{Diagnostics, DiagnosticsPlugin};
+
+mod framing;
+use framing::{FrameRequest, FrameTarget, FramingPlugin};
+
+mod walkthrough;
+use walkthrough::{WalkthroughPlugin, WalkthroughSettings};
+
+mod bookmarks;
+use bookmarks::{Bookmarks, BookmarksPlugin};
 
 
 fn handle_keyboard(
     keyboard_input: Res<Input<KeyCode>>,
+    egui_context: Res<EguiContext>,
+    mut sm: ResMut<SiteMap>,
+    mut frame: ResMut<FrameRequest>,
     mut query: Query<&mut FlexibleProjection>,
 ) {
-    let mut projection = query.single_mut();
-    if keyboard_input.just_pressed(KeyCode::Key2) {
-        projection.set_mode(ProjectionMode::Orthographic);
+    let control = keyboard_input.pressed(KeyCode::LControl) || keyboard_input.pressed(KeyCode::RControl);
+    let shift = keyboard_input.pressed(KeyCode::LShift) || keyboard_input.pressed(KeyCode::RShift);
+    // leave Ctrl+Z to text fields that are being edited
+    if control && keyboard_input.just_pressed(KeyCode::Z) && !egui_context.ctx().wants_keyboard_input() {
+        if shift {
+            sm.redo();
+        } else {
+            sm.undo();
+        }
     }
 
-    if keyboard_input.just_pressed(KeyCode::Key3) {
-        projection.set_mode(ProjectionMode::Perspective);
+    if !egui_context.ctx().wants_keyboard_input() {
+        if keyboard_input.just_pressed(KeyCode::Home) {
+            frame.0 = Some(FrameTarget::All);
+        }
+        if keyboard_input.just_pressed(KeyCode::F) {
+            frame.0 = Some(FrameTarget::Selection);
+        }
+
+        let mut projection = query.single_mut();
+        if keyboard_input.just_pressed(KeyCode::Key2) {
+            projection.set_mode(ProjectionMode::Orthographic);
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Key3) {
+            projection.set_mode(ProjectionMode::Perspective);
+        }
+
+        if keyboard_input.just_pressed(KeyCode::Key4) {
+            projection.set_mode(ProjectionMode::Walkthrough);
+        }
     }
 }
 
 fn egui_ui(
     mut sm: ResMut<SiteMap>,
+    mut status: ResMut<SiteMapStatus>,
+    mut tools: ResMut<EditorTools>,
+    mut diagnostics: ResMut<Diagnostics>,
+    mut frame: ResMut<FrameRequest>,
+    mut walkthrough: ResMut<WalkthroughSettings>,
+    mut bookmarks: ResMut<Bookmarks>,
+    mut export_lanes: Local<bool>,
     egui_context: ResMut<EguiContext>,
     mut query: Query<&mut FlexibleProjection>,
-    commands: Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
-    asset_server: Res<AssetServer>,
 ) {
     let mut projection = query.single_mut();
     egui::TopBottomPanel::top("top_panel")
@@ -78,19 +136,85 @@ fn egui_ui(
                 egui::menu::bar(ui, |ui| {
                     egui::menu::menu(ui, "File", |ui| {
                         if ui.button("Load demo").clicked() {
-                            sm.load_demo();
-                            sm.spawn(commands, meshes, materials, asset_server);
+                            if let Err(e) = sm.load_demo() {
+                                status.error = Some(e.to_string());
+                            }
+                        }
+
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if ui.button("Save").clicked() {
+                            if let Some(filename) = building_file(&sm, &mut status, "save it") {
+                                if let Err(e) = sm.save(filename) {
+                                    status.error = Some(e.to_string());
+                                }
+                            }
+                        }
+
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if ui.button("Export nav graphs").clicked() {
+                            if let Some(filename) = building_file(&sm, &mut status, "export from it") {
+                                let dir = std::path::Path::new(&filename).with_file_name("nav_graphs");
+                                let result = nav_graph::write_nav_graphs(&sm.to_building_map(), &dir);
+                                report_written(&mut status, result);
+                            }
+                        }
+
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if ui.button("Export world").clicked() {
+                            if let Some(filename) = building_file(&sm, &mut status, "export from it") {
+                                let dir = std::path::Path::new(&filename).with_file_name("world");
+                                let result = sdf::write_world(&sm.to_building_map(), &dir);
+                                report_written(&mut status, result);
+                            }
                         }
 
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if ui.button("Export glTF").clicked() {
+                            if let Some(filename) = building_file(&sm, &mut status, "export from it") {
+                                let stem = filename.trim_end_matches(".yaml").trim_end_matches(".building");
+                                let path = std::path::PathBuf::from(format!("{}.glb", stem));
+                                let result = glb::write_glb(&sm.to_building_map(), &path, *export_lanes);
+                                report_written(&mut status, result.map(|()| vec![path]));
+                            }
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        ui.checkbox(&mut *export_lanes, "with lanes");
+
                         #[cfg(not(target_arch = "wasm32"))]
                         if ui.button("Quit").clicked() {
                             std::process::exit(0);
                         }
                     });
+
+                    egui::menu::menu(ui, "Edit", |ui| {
+                        if ui.add(egui::Button::new("Undo (Ctrl+Z)").enabled(sm.can_undo())).clicked() {
+                            sm.undo();
+                        }
+                        if ui.add(egui::Button::new("Redo (Ctrl+Shift+Z)").enabled(sm.can_redo())).clicked() {
+                            sm.redo();
+                        }
+                        ui.separator();
+                        if ui.button("Check map").clicked() {
+                            diagnostics.open = true;
+                        }
+                    });
+
+                    egui::menu::menu(ui, "View", |ui| {
+                        if ui.button("Frame All (Home)").clicked() {
+                            frame.0 = Some(FrameTarget::All);
+                        }
+                        if ui.button("Frame Selection (F)").clicked() {
+                            frame.0 = Some(FrameTarget::Selection);
+                        }
+                        ui.separator();
+                        if ui.button("Bookmarks").clicked() {
+                            bookmarks.open = true;
+                        }
+                    });
                 });
 
                 ui.horizontal(|ui| {
-                    ui.label("[toolbar buttons]");
+                    tools.toolbar(ui, &sm);
                     ui.separator();
                     if ui.add(egui::SelectableLabel::new(projection.mode == ProjectionMode::Orthographic, "2D")).clicked() {
                         projection.set_mode(ProjectionMode::Orthographic);
@@ -98,15 +222,82 @@ fn egui_ui(
                     if ui.add(egui::SelectableLabel::new(projection.mode == ProjectionMode::Perspective, "3D")).clicked() {
                         projection.set_mode(ProjectionMode::Perspective);
                     }
+                    if ui.add(egui::SelectableLabel::new(projection.mode == ProjectionMode::Walkthrough, "Walk")).clicked() {
+                        projection.set_mode(ProjectionMode::Walkthrough);
+                    }
+                    if projection.mode == ProjectionMode::Walkthrough {
+                        ui.label("sensor height");
+                        ui.add(egui::DragValue::new(&mut walkthrough.sensor_height)
+                            .speed(0.01)
+                            .clamp_range(0.05..=3.0)
+                            .suffix(" m"));
+                        ui.label("WASD to walk, right-drag to look");
+                    }
                 });
             });
         });
+
+    let mut dismissed = false;
+    if let Some(error) = &status.error {
+        egui::Window::new("Error")
+            .collapsible(false)
+            .resizable(false)
+            .show(egui_context.ctx(), |ui| {
+                ui.label(error);
+                if ui.button("OK").clicked() {
+                    dismissed = true;
+                }
+            });
+    }
+    if dismissed {
+        status.error = None;
+    }
+
+    let mut dismissed = false;
+    if let Some(message) = &status.message {
+        egui::Window::new("Done")
+            .collapsible(false)
+            .resizable(false)
+            .show(egui_context.ctx(), |ui| {
+                ui.label(message);
+                if ui.button("OK").clicked() {
+                    dismissed = true;
+                }
+            });
+    }
+    if dismissed {
+        status.message = None;
+    }
+}
+
+/// The file the map was loaded from, which saving and exporting need. If
+/// there is none, tells the user they cannot `action` and returns None.
+#[cfg(not(target_arch = "wasm32"))]
+fn building_file(sm: &SiteMap, status: &mut SiteMapStatus, action: &str) -> Option<String> {
+    let filename = sm.filename();
+    if filename.is_empty() {
+        status.error = Some(format!(
+            "This map was not loaded from a file; \
+             start the editor with a .building.yaml path to {}.", action));
+        return None;
+    }
+    Some(filename.to_string())
+}
+
+/// Lists the files an export wrote, or shows why it failed.
+#[cfg(not(target_arch = "wasm32"))]
+fn report_written(status: &mut SiteMapStatus, result: Result<Vec<std::path::PathBuf>, site_map::SiteMapError>) {
+    match result {
+        Ok(written) => {
+            let lines: Vec<String> = written.iter().map(|path| format!("wrote [{}]", path.display())).collect();
+            status.message = Some(lines.join("\n"));
+        }
+        Err(e) => status.error = Some(e.to_string()),
+    }
 }
 
 fn setup(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
 ) {
     println!("entering setup() startup system...");
 
@@ -126,21 +317,6 @@ fn setup(
     });
     */
 
-    // plane
-    commands.spawn_bundle(PbrBundle {
-        mesh: meshes.add(Mesh::from(shape::Plane { size: 100.0 })),
-        //material: materials.add(Color::rgb(0.3, 0.7, 0.3).into()),
-        material: materials.add(StandardMaterial {
-            base_color: Color::rgb(0.3, 0.3, 0.3),
-            ..Default::default()
-        }),
-        transform: Transform {
-            rotation: Quat::from_rotation_x(1.57),
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-
     commands.insert_resource(AmbientLight {
         color: Color::WHITE,
         brightness: 0.001,
@@ -177,8 +353,31 @@ fn check_browser_window_size(mut windows: ResMut<Windows>) {
     }
 }
 
+/// Entry point of the `traffic_editor_iii` binary: runs the editor, or a
+/// headless command without creating a window. Returns the exit code.
+pub fn run_with_args(args: &[String]) -> i32 {
+    let command = match cli::Command::parse(args.get(1..).unwrap_or(&[])) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("{}\n{}", message, cli::USAGE);
+            return 2;
+        }
+    };
+    if command.is_headless() {
+        return cli::run_headless(&command);
+    }
+    run_editor(command);
+    0
+}
+
 #[wasm_bindgen]
 pub fn run() {
+    run_editor(cli::Command::Edit { filename: None });
+}
+
+/// Opens the editor. The command is kept as a resource, for the site map to
+/// know which file to load.
+fn run_editor(command: cli::Command) {
 
     #[cfg(target_arch = "wasm32")]
     App::new()
@@ -188,6 +387,7 @@ pub fn run() {
             //vsync: false,
             ..Default::default()
         })
+        .insert_resource(command)
         .add_plugins(PipelinedDefaultPlugins)
         .insert_resource( DirectionalLightShadowMap {
             size: 1024
@@ -195,6 +395,13 @@ pub fn run() {
         .add_plugin(SuperCameraPlugin)
         .add_startup_system(setup)
         .add_plugin(SiteMapPlugin)
+        .add_plugin(SelectionPlugin)
+        .add_plugin(ToolsPlugin)
+        .add_plugin(PropertiesPlugin)
+        .add_plugin(DiagnosticsPlugin)
+        .add_plugin(FramingPlugin)
+        .add_plugin(WalkthroughPlugin)
+        .add_plugin(BookmarksPlugin)
         .add_system(handle_keyboard)
         .add_plugin(EguiPlugin)
         .add_system(egui_ui)
@@ -214,6 +421,7 @@ pub fn run() {
             //vsync: false,
             ..Default::default()
         })
+        .insert_resource(command)
         .insert_resource( DirectionalLightShadowMap {
             size: 2048
         })
@@ -224,6 +432,13 @@ pub fn run() {
         .add_plugin(SuperCameraPlugin)
         .add_startup_system(setup)
         .add_plugin(SiteMapPlugin)
+        .add_plugin(SelectionPlugin)
+        .add_plugin(ToolsPlugin)
+        .add_plugin(PropertiesPlugin)
+        .add_plugin(DiagnosticsPlugin)
+        .add_plugin(FramingPlugin)
+        .add_plugin(WalkthroughPlugin)
+        .add_plugin(BookmarksPlugin)
         .add_system(handle_keyboard)
         .add_plugin(EguiPlugin)
         .add_system(egui_ui)
@@ -1,3 +1,4 @@
 fn main() {
-    libtraffic_editor_iii::run();
+    let args: Vec<String> = std::env::args().collect();
+    std::process::exit(libtraffic_editor_iii::run_with_args(&args));
 }
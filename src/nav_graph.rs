@@ -0,0 +1,361 @@
+// Navigation graphs in the YAML format read by the RMF fleet adapters,
+// one per `graph_idx`. This replaces the nav-graph output of the Python
+// building_map_generator.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Serializer};
+use serde_yaml::Value;
+
+use crate::building_map::{BuildingMap, LaneOrientation, Level, Lift, Param, SiteMapError};
+
+/// The graph of one fleet: the waypoints and lanes of every level, with
+/// coordinates in metres (`x` along the drawing, `y` pointing up it).
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct NavGraph {
+    pub building_name: String,
+    pub levels: BTreeMap<String, NavLevel>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub lifts: BTreeMap<String, NavLift>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct NavLevel {
+    pub lanes: Vec<NavLane>,
+    pub vertices: Vec<NavVertex>,
+}
+
+/// Written as `[x, y, {properties}]`.
+#[derive(Debug, Clone)]
+pub struct NavVertex {
+    pub x: f64,
+    pub y: f64,
+    pub properties: BTreeMap<String, Value>,
+}
+
+/// A one-way lane between two of the level's `NavVertex` indices, written
+/// as `[start, end, {properties}]`.
+#[derive(Debug, Clone)]
+pub struct NavLane {
+    pub start: usize,
+    pub end: usize,
+    pub properties: BTreeMap<String, Value>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct NavLift {
+    /// Centre of the cabin and its yaw, as `[x, y, yaw]`.
+    pub position: [f64; 3],
+    /// Width and depth of the cabin, in metres.
+    pub dims: [f64; 2],
+    pub levels: Vec<String>,
+}
+
+impl Serialize for NavVertex {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.x, self.y, &self.properties).serialize(serializer)
+    }
+}
+
+impl Serialize for NavLane {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (self.start, self.end, &self.properties).serialize(serializer)
+    }
+}
+
+impl NavGraph {
+    /// The graph of every lane with the given `graph_idx`. Only the vertices
+    /// those lanes use are included, renumbered in the order they are first
+    /// used. Two-way lanes are written as a pair of one-way lanes.
+    ///
+    /// A lane that crosses a door carries the door's name. Vertices inside
+    /// a lift cabin carry the lift's name, which is where the fleet adapters
+    /// look for lift events.
+    pub fn new(building: &BuildingMap, graph_idx: i64) -> NavGraph {
+        let cabins: Vec<Cabin> = building.lifts.values()
+            .filter_map(|lift| Cabin::new(lift, building))
+            .collect();
+        let levels = building.levels.iter()
+            .map(|(name, level)| (name.clone(), nav_level(level, graph_idx, &cabins)))
+            .collect();
+        let lifts = cabins.iter()
+            .map(|cabin| (cabin.name.clone(), NavLift {
+                position: [cabin.center.0, cabin.center.1, cabin.yaw],
+                dims: [cabin.width, cabin.depth],
+                levels: cabin.levels.clone(),
+            }))
+            .collect();
+        NavGraph {
+            building_name: building.name.clone(),
+            levels,
+            lifts,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.levels.values().all(|level| level.lanes.is_empty())
+    }
+
+    pub fn to_yaml(&self) -> Result<String, SiteMapError> {
+        serde_yaml::to_string(self).map_err(|e| SiteMapError::Serialize(e.to_string()))
+    }
+}
+
+/// The graph of each `graph_idx` that has any lanes.
+pub fn nav_graphs(building: &BuildingMap) -> BTreeMap<i64, NavGraph> {
+    let mut graph_indices: Vec<i64> = building.levels.values()
+        .flat_map(|level| level.lanes.iter().map(|lane| lane.graph_idx))
+        .collect();
+    graph_indices.sort_unstable();
+    graph_indices.dedup();
+    graph_indices.into_iter()
+        .map(|i| (i, NavGraph::new(building, i)))
+        .filter(|(_, graph)| !graph.is_empty())
+        .collect()
+}
+
+/// Writes each graph to `<dir>/<graph_idx>.yaml`, creating `dir` if needed,
+/// and returns the files written.
+pub fn write_nav_graphs(building: &BuildingMap, dir: &Path) -> Result<Vec<PathBuf>, SiteMapError> {
+    let io_error = |path: &Path| {
+        let filename = path.display().to_string();
+        move |source| SiteMapError::Io { filename, source }
+    };
+    fs::create_dir_all(dir).map_err(io_error(dir))?;
+    let mut written = Vec::new();
+    for (graph_idx, graph) in nav_graphs(building) {
+        let path = dir.join(format!("{}.yaml", graph_idx));
+        fs::write(&path, graph.to_yaml()?).map_err(io_error(&path))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn param_value(param: &Param) -> Value {
+    match param {
+        Param::String(s) => Value::from(s.as_str()),
+        Param::Int(i) => Value::from(*i),
+        Param::Double(d) => Value::from(*d),
+        Param::Bool(b) => Value::from(*b),
+    }
+}
+
+/// The footprint of a lift's cabin, in metres.
+pub struct Cabin {
+    pub name: String,
+    center: (f64, f64),
+    yaw: f64,
+    width: f64,
+    depth: f64,
+    levels: Vec<String>,
+}
+
+impl Cabin {
+    pub fn new(lift: &Lift, building: &BuildingMap) -> Option<Cabin> {
+        let reference = building.levels.get(&lift.reference_floor_name)
+            .or_else(|| building.levels.values().next())?;
+        let scale = reference.scale();
+        Some(Cabin {
+            name: lift.name.clone(),
+            center: (lift.x * scale, -lift.y * scale),
+            yaw: lift.yaw,
+            width: lift.width,
+            depth: lift.depth,
+            levels: lift.served_levels(&building.levels),
+        })
+    }
+
+    /// Whether a point of `level`, in metres, is inside the cabin.
+    pub fn contains(&self, level: &str, (x, y): (f64, f64)) -> bool {
+        if !self.levels.iter().any(|l| l == level) {
+            return false;
+        }
+        // into the cabin's own frame
+        let (dx, dy) = (x - self.center.0, y - self.center.1);
+        let (sin, cos) = self.yaw.sin_cos();
+        let along = dx * cos + dy * sin;
+        let across = -dx * sin + dy * cos;
+        along.abs() <= self.width / 2. && across.abs() <= self.depth / 2.
+    }
+}
+
+/// Whether segments ab and cd cross each other.
+pub fn segments_intersect(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    let cross = |o: (f64, f64), p: (f64, f64), q: (f64, f64)| {
+        (p.0 - o.0) * (q.1 - o.1) - (p.1 - o.1) * (q.0 - o.0)
+    };
+    let (d1, d2) = (cross(a, b, c), cross(a, b, d));
+    let (d3, d4) = (cross(c, d, a), cross(c, d, b));
+    d1 * d2 < 0. && d3 * d4 < 0.
+}
+
+fn reversed(orientation: LaneOrientation) -> LaneOrientation {
+    match orientation {
+        LaneOrientation::Any => LaneOrientation::Any,
+        LaneOrientation::Forward => LaneOrientation::Backward,
+        LaneOrientation::Backward => LaneOrientation::Forward,
+    }
+}
+
+fn nav_level(level: &Level, graph_idx: i64, cabins: &[Cabin]) -> NavLevel {
+    let scale = level.scale();
+    let metres = |i: usize| {
+        let v = &level.vertices[i];
+        (v.x * scale, -v.y * scale)
+    };
+    let lanes: Vec<_> = level.lanes.iter()
+        .filter(|lane| lane.graph_idx == graph_idx)
+        .filter(|lane| lane.start < level.vertices.len() && lane.end < level.vertices.len())
+        .collect();
+
+    let mut nav = NavLevel::default();
+    let mut renumbered: BTreeMap<usize, usize> = BTreeMap::new();
+    for lane in &lanes {
+        for i in [lane.start, lane.end] {
+            if renumbered.contains_key(&i) {
+                continue;
+            }
+            renumbered.insert(i, nav.vertices.len());
+            let v = &level.vertices[i];
+            let (x, y) = metres(i);
+            let mut properties: BTreeMap<String, Value> = v.params.iter()
+                .map(|(key, param)| (key.clone(), param_value(param)))
+                .collect();
+            properties.insert("name".into(), Value::from(v.name.as_str()));
+            if let Some(cabin) = cabins.iter().find(|c| c.contains(&level.name, (x, y))) {
+                properties.insert("lift".into(), Value::from(cabin.name.as_str()));
+            }
+            nav.vertices.push(NavVertex { x, y, properties });
+        }
+    }
+
+    for lane in lanes {
+        let (start, end) = (metres(lane.start), metres(lane.end));
+        let mut properties = BTreeMap::new();
+        let door = level.doors.iter().find(|door| {
+            door.start < level.vertices.len()
+                && door.end < level.vertices.len()
+                && segments_intersect(start, end, metres(door.start), metres(door.end))
+        });
+        if let Some(door) = door {
+            properties.insert("door_name".into(), Value::from(door.name.as_str()));
+        }
+        if let Some(speed_limit) = lane.speed_limit {
+            properties.insert("speed_limit".into(), Value::from(speed_limit));
+        }
+        if !lane.demo_mock_floor_name.is_empty() {
+            properties.insert("demo_mock_floor_name".into(), Value::from(lane.demo_mock_floor_name.as_str()));
+        }
+        if !lane.demo_mock_lift_name.is_empty() {
+            properties.insert("demo_mock_lift_name".into(), Value::from(lane.demo_mock_lift_name.as_str()));
+        }
+
+        // a lane that leads to a dock carries the dock's name
+        let dock = |i: usize| level.vertices[i].params.get("dock_name").map(param_value);
+        let with = |mut properties: BTreeMap<String, Value>, orientation: LaneOrientation, dock: Option<Value>| {
+            if orientation != LaneOrientation::Any {
+                properties.insert("orientation_constraint".into(), Value::from(orientation.as_str()));
+            }
+            if let Some(dock) = dock {
+                properties.insert("dock_name".into(), dock);
+            }
+            properties
+        };
+        let (start_idx, end_idx) = (renumbered[&lane.start], renumbered[&lane.end]);
+        let forward = with(properties.clone(), lane.orientation, dock(lane.end));
+        nav.lanes.push(NavLane { start: start_idx, end: end_idx, properties: forward });
+        if lane.bidirectional {
+            let backward = with(properties, reversed(lane.orientation), dock(lane.start));
+            nav.lanes.push(NavLane { start: end_idx, end: start_idx, properties: backward });
+        }
+    }
+    nav
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Three waypoints in a row on graph 0, the first two joined by a
+    /// two-way lane through a door and the last two by a one-way lane, and
+    /// a lane on graph 1 into a lift.
+    const BUILDING: &str = "\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices:
+      - [0, 0, 0, a]
+      - [100, 0, 0, b]
+      - [200, 0, 0, c]
+      - [0, 100, 0, unused]
+      - [300, 0, 0, d]
+      - [50, -50, 0, '']
+      - [50, 50, 0, '']
+    lanes:
+      - [0, 1, {bidirectional: [4, true], graph_idx: [2, 0]}]
+      - [1, 2, {bidirectional: [4, false], graph_idx: [2, 0], orientation: [1, forward]}]
+      - [2, 4, {bidirectional: [4, false], graph_idx: [2, 1]}]
+    doors:
+      - [5, 6, {name: [1, door1], type: [1, sliding]}]
+lifts:
+  lift1:
+    depth: 1
+    width: 1
+    x: 300
+    y: 0
+    reference_floor_name: L1
+    level_doors: {L1: []}
+";
+
+    fn graph_yaml(graph_idx: i64) -> Value {
+        let building = BuildingMap::from_yaml(BUILDING).unwrap();
+        let graphs = nav_graphs(&building);
+        serde_yaml::from_str(&graphs[&graph_idx].to_yaml().unwrap()).unwrap()
+    }
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn one_graph_per_graph_idx() {
+        let building = BuildingMap::from_yaml(BUILDING).unwrap();
+        let graphs = nav_graphs(&building);
+        assert_eq!(graphs.keys().copied().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(graphs[&0].building_name, "test");
+    }
+
+    #[test]
+    fn vertices_are_renumbered_in_order_of_use() {
+        let level = &graph_yaml(0)["levels"]["L1"];
+        assert_eq!(level["vertices"], yaml("\
+- [0.0, -0.0, {name: a}]
+- [1.0, -0.0, {name: b}]
+- [2.0, -0.0, {name: c}]
+"));
+        let level = &graph_yaml(1)["levels"]["L1"];
+        assert_eq!(level["vertices"][0][2]["name"], Value::from("c"));
+        assert_eq!(level["vertices"][1][2]["name"], Value::from("d"));
+    }
+
+    #[test]
+    fn two_way_lanes_are_written_both_ways() {
+        let level = &graph_yaml(0)["levels"]["L1"];
+        assert_eq!(level["lanes"], yaml("\
+- [0, 1, {door_name: door1}]
+- [1, 0, {door_name: door1}]
+- [1, 2, {orientation_constraint: forward}]
+"));
+    }
+
+    #[test]
+    fn lifts_are_listed_with_the_vertices_in_them() {
+        let graph = graph_yaml(1);
+        assert_eq!(graph["levels"]["L1"]["vertices"][1][2]["lift"], Value::from("lift1"));
+        assert!(graph["levels"]["L1"]["vertices"][0][2].get("lift").is_none());
+        assert_eq!(graph["lifts"]["lift1"], yaml("{position: [3.0, -0.0, 0.0], dims: [1.0, 1.0], levels: [L1]}"));
+    }
+}
@@ -0,0 +1,302 @@
+use bevy::{
+    ecs::prelude::*,
+    prelude::{App, Plugin},
+};
+use bevy_egui::{egui, EguiContext};
+
+use crate::building_map::{
+    Door, DoorType, Lane, LaneOrientation, MotionAxis, Param, Params, Vertex, DOOR_KEYS, LANE_KEYS,
+    VERTEX_FLAGS, VERTEX_NAMES,
+};
+use crate::edit::{Edit, Element};
+use crate::selection::Selection;
+use crate::site_map::{ElementKind, SiteMap};
+
+/// Edits any element's parameter map: the values in place, plus removing
+/// entries and adding new ones of any type. Keys in `hidden` are edited
+/// elsewhere in the panel, so they are neither listed nor addable.
+pub struct ParamsInspector {
+    new_name: String,
+    new_value: Param,
+}
+
+impl Default for ParamsInspector {
+    fn default() -> Self {
+        ParamsInspector {
+            new_name: String::new(),
+            new_value: Param::String(String::new()),
+        }
+    }
+}
+
+impl ParamsInspector {
+    pub fn show(&mut self, ui: &mut egui::Ui, id_source: &str, params: &mut Params, hidden: &[&str]) {
+        let mut removed = None;
+        egui::Grid::new(id_source).num_columns(3).show(ui, |ui| {
+            for (name, value) in params.iter_mut() {
+                if hidden.contains(&name.as_str()) {
+                    continue;
+                }
+                ui.label(name);
+                param_value_widget(ui, value);
+                if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                    removed = Some(name.clone());
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(name) = removed {
+            params.remove(&name);
+        }
+
+        ui.horizontal(|ui| {
+            ui.add(egui::TextEdit::singleline(&mut self.new_name).desired_width(80.));
+            egui::ComboBox::from_id_source((id_source, "new_param_type"))
+                .selected_text(self.new_value.type_name())
+                .show_ui(ui, |ui| {
+                    let kinds = [
+                        Param::String(String::new()),
+                        Param::Int(0),
+                        Param::Double(0.),
+                        Param::Bool(false),
+                    ];
+                    for kind in kinds {
+                        let name = kind.type_name();
+                        ui.selectable_value(&mut self.new_value, kind, name);
+                    }
+                });
+            let name = self.new_name.trim();
+            let addable = !name.is_empty() && !params.contains_key(name) && !hidden.contains(&name);
+            if ui.add(egui::Button::new("Add").enabled(addable)).clicked() {
+                params.insert(name.to_string(), self.new_value.clone());
+                self.new_name.clear();
+            }
+        });
+    }
+}
+
+fn param_value_widget(ui: &mut egui::Ui, value: &mut Param) {
+    match value {
+        Param::String(s) => {
+            ui.text_edit_singleline(s);
+        }
+        Param::Int(i) => {
+            ui.add(egui::DragValue::new(i));
+        }
+        Param::Double(d) => {
+            ui.add(egui::DragValue::new(d).speed(0.01));
+        }
+        Param::Bool(b) => {
+            ui.checkbox(b, "");
+        }
+    }
+}
+
+fn lane_properties(ui: &mut egui::Ui, lane: &mut Lane) {
+    egui::Grid::new("lane_properties").num_columns(2).show(ui, |ui| {
+        ui.label("Vertices");
+        ui.label(format!("{} → {}", lane.start, lane.end));
+        ui.end_row();
+
+        ui.label("Bidirectional");
+        ui.checkbox(&mut lane.bidirectional, "");
+        ui.end_row();
+
+        ui.label("Graph");
+        ui.add(egui::DragValue::new(&mut lane.graph_idx).clamp_range(0..=99));
+        ui.end_row();
+
+        ui.label("Orientation");
+        egui::ComboBox::from_id_source("lane_orientation")
+            .selected_text(match lane.orientation {
+                LaneOrientation::Any => "any",
+                other => other.as_str(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut lane.orientation, LaneOrientation::Any, "any");
+                ui.selectable_value(&mut lane.orientation, LaneOrientation::Forward, "forward");
+                ui.selectable_value(&mut lane.orientation, LaneOrientation::Backward, "backward");
+            });
+        ui.end_row();
+
+        ui.label("Speed limit");
+        ui.horizontal(|ui| {
+            let mut limited = lane.speed_limit.is_some();
+            ui.checkbox(&mut limited, "");
+            if limited {
+                let speed_limit = lane.speed_limit.get_or_insert(0.5);
+                ui.add(egui::DragValue::new(speed_limit).speed(0.05).clamp_range(0.0..=10.0).suffix(" m/s"));
+            } else {
+                lane.speed_limit = None;
+            }
+        });
+        ui.end_row();
+
+        ui.label("Mock floor");
+        ui.text_edit_singleline(&mut lane.demo_mock_floor_name);
+        ui.end_row();
+
+        ui.label("Mock lift");
+        ui.text_edit_singleline(&mut lane.demo_mock_lift_name);
+        ui.end_row();
+    });
+}
+
+fn door_properties(ui: &mut egui::Ui, door: &mut Door) {
+    egui::Grid::new("door_properties").num_columns(2).show(ui, |ui| {
+        ui.label("Vertices");
+        ui.label(format!("{} → {}", door.start, door.end));
+        ui.end_row();
+
+        ui.label("Name");
+        ui.text_edit_singleline(&mut door.name);
+        ui.end_row();
+
+        ui.label("Type");
+        egui::ComboBox::from_id_source("door_type")
+            .selected_text(door.door_type.as_str())
+            .show_ui(ui, |ui| {
+                let types = [DoorType::Hinged, DoorType::DoubleHinged, DoorType::Sliding, DoorType::DoubleSliding];
+                for door_type in types {
+                    ui.selectable_value(&mut door.door_type, door_type, door_type.as_str());
+                }
+            });
+        ui.end_row();
+
+        ui.label("Motion axis");
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut door.motion_axis, MotionAxis::Start, "start");
+            ui.selectable_value(&mut door.motion_axis, MotionAxis::End, "end");
+        });
+        ui.end_row();
+
+        ui.label("Motion degrees");
+        ui.add(egui::DragValue::new(&mut door.motion_degrees).clamp_range(0.0..=180.0).suffix("°"));
+        ui.end_row();
+
+        ui.label("Motion direction");
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut door.motion_direction, 1, "1");
+            ui.selectable_value(&mut door.motion_direction, -1, "-1");
+        });
+        ui.end_row();
+
+        ui.label("Right/left ratio");
+        ui.add(egui::DragValue::new(&mut door.right_left_ratio).speed(0.01).clamp_range(0.1..=10.0));
+        ui.end_row();
+    });
+}
+
+fn vertex_properties(ui: &mut egui::Ui, vertex: &mut Vertex) {
+    egui::Grid::new("vertex_properties").num_columns(2).show(ui, |ui| {
+        ui.label("Name");
+        ui.text_edit_singleline(&mut vertex.name);
+        ui.end_row();
+
+        for key in VERTEX_FLAGS {
+            let mut value = vertex.flag(key);
+            ui.label(key);
+            if ui.checkbox(&mut value, "").changed() {
+                vertex.set_flag(key, value);
+            }
+            ui.end_row();
+        }
+
+        for key in VERTEX_NAMES {
+            let mut value = vertex.text(key).to_string();
+            ui.label(key);
+            if ui.text_edit_singleline(&mut value).changed() {
+                vertex.set_text(key, &value);
+            }
+            ui.end_row();
+        }
+    });
+}
+
+/// Shows the properties of the selected element and records every change
+/// as an edit. A change made by typing or dragging in a field is undone as
+/// one step, once the field is let go of.
+fn properties_panel(
+    mut sm: ResMut<SiteMap>,
+    selection: Res<Selection>,
+    egui_context: Res<EguiContext>,
+    mut editing: Local<bool>,
+    mut inspector: Local<ParamsInspector>,
+) {
+    let ctx = egui_context.ctx();
+    if *editing && !ctx.is_using_pointer() && ctx.memory().focus().is_none() {
+        *editing = false;
+        sm.end_gesture();
+    }
+
+    let id = match &selection.selected {
+        Some(id) => id,
+        None => return,
+    };
+    let before = match sm.levels().get(&id.level).and_then(|l| Element::get(l, id.kind, id.index)) {
+        Some(element) => element,
+        None => return,
+    };
+
+    let mut after = before.clone();
+    egui::SidePanel::right("properties").show(ctx, |ui| {
+        match &mut after {
+            Element::Vertex(vertex) => {
+                ui.heading(format!("Vertex {}", id.index));
+                vertex_properties(ui, vertex);
+                ui.separator();
+                ui.label("Other parameters");
+                let hidden: Vec<&str> = VERTEX_FLAGS.iter().chain(VERTEX_NAMES.iter()).copied().collect();
+                inspector.show(ui, "vertex_params", &mut vertex.params, &hidden);
+            }
+            Element::Lane(lane) => {
+                ui.heading(format!("Lane {}", id.index));
+                lane_properties(ui, lane);
+                ui.separator();
+                ui.label("Other parameters");
+                inspector.show(ui, "lane_params", &mut lane.params, &LANE_KEYS);
+            }
+            Element::Door(door) => {
+                ui.heading(format!("Door {}", id.index));
+                door_properties(ui, door);
+                ui.separator();
+                ui.label("Other parameters");
+                inspector.show(ui, "door_params", &mut door.params, &DOOR_KEYS);
+            }
+            Element::Wall(edge) | Element::Measurement(edge) => {
+                let kind = if matches!(id.kind, ElementKind::Wall) { "Wall" } else { "Measurement" };
+                ui.heading(format!("{} {}", kind, id.index));
+                ui.label(format!("Vertices {} → {}", edge.start, edge.end));
+                ui.separator();
+                ui.label("Parameters");
+                inspector.show(ui, "edge_params", &mut edge.params, &[]);
+            }
+            Element::Floor(floor) | Element::Hole(floor) => {
+                ui.heading(format!("Floor {}", id.index));
+                ui.label(format!("{} vertices", floor.vertices.len()));
+                ui.separator();
+                ui.label("Parameters");
+                inspector.show(ui, "floor_params", &mut floor.parameters, &[]);
+            }
+        }
+    });
+
+    if before != after {
+        sm.apply_gesture_step(Edit::Replace {
+            level: id.level.clone(),
+            index: id.index,
+            before,
+            after,
+        });
+        *editing = true;
+    }
+}
+
+#[derive(Default)]
+pub struct PropertiesPlugin;
+
+impl Plugin for PropertiesPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(properties_panel);
+    }
+}
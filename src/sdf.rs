@@ -0,0 +1,294 @@
+// Writes a building as an SDF world for Gazebo. Each level becomes a model
+// of its own, with its walls and floors as OBJ meshes; doors become jointed
+// models and the level's models are included by name.
+//
+// The output directory holds the world file and a `models` directory, which
+// has to be on the simulator's model path (GAZEBO_MODEL_PATH or
+// IGN_GAZEBO_RESOURCE_PATH) together with the models the building uses.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::building_map::{BuildingMap, Door, DoorType, Level, SiteMapError};
+use crate::geometry::{door_panels, floor_meshes, metres, wall_mesh, MeshData, DOOR_HEIGHT, DOOR_THICKNESS};
+
+/// Writes `<dir>/<building>.world` and the level models it uses, and
+/// returns the files written.
+pub fn write_world(building: &BuildingMap, dir: &Path) -> Result<Vec<PathBuf>, SiteMapError> {
+    let world_name = if building.name.is_empty() { "building" } else { &building.name };
+    let mut files = Vec::new();
+    for level in building.levels.values() {
+        let model_name = format!("{}_{}", world_name, level.name);
+        let model_dir = dir.join("models").join(&model_name);
+        let mut meshes = Vec::new();
+        let walls = wall_mesh(level);
+        if !walls.is_empty() {
+            meshes.push(("walls", walls.to_obj("walls")));
+        }
+        let mut floors = MeshData::default();
+        for (mesh, _texture) in floor_meshes(level) {
+            floors.append(&mesh);
+        }
+        if !floors.is_empty() {
+            meshes.push(("floors", floors.to_obj("floors")));
+        }
+        let links: Vec<&str> = meshes.iter().map(|(name, _)| *name).collect();
+        for (name, obj) in &meshes {
+            files.push((model_dir.join("meshes").join(format!("{}.obj", name)), obj.clone()));
+        }
+        files.push((model_dir.join("model.sdf"), level_model(&model_name, &links)));
+        files.push((model_dir.join("model.config"), model_config(&model_name)));
+    }
+    files.push((dir.join(format!("{}.world", world_name)), world(building, world_name)));
+
+    let mut written = Vec::new();
+    for (path, text) in files {
+        let io_error = |source| SiteMapError::Io { filename: path.display().to_string(), source };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(io_error)?;
+        }
+        fs::write(&path, text).map_err(io_error)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn model_config(model_name: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?>\n\
+         <model>\n  \
+           <name>{0}</name>\n  \
+           <version>1.0</version>\n  \
+           <sdf version=\"1.7\">model.sdf</sdf>\n  \
+           <description>Generated by traffic_editor_iii</description>\n\
+         </model>\n",
+        escape(model_name))
+}
+
+/// A static model with one link per mesh, each mesh both seen and collided
+/// with.
+fn level_model(model_name: &str, links: &[&str]) -> String {
+    let mut sdf = format!(
+        "<?xml version=\"1.0\"?>\n<sdf version=\"1.7\">\n  <model name=\"{}\">\n    <static>true</static>\n",
+        escape(model_name));
+    for link in links {
+        let mesh = format!(
+            "<geometry><mesh><uri>model://{}/meshes/{}.obj</uri></mesh></geometry>",
+            escape(model_name), link);
+        sdf += &format!(
+            "    <link name=\"{0}\">\n      \
+               <visual name=\"{0}_visual\">{1}</visual>\n      \
+               <collision name=\"{0}_collision\">{1}</collision>\n    \
+             </link>\n",
+            link, mesh);
+    }
+    sdf += "  </model>\n</sdf>\n";
+    sdf
+}
+
+fn world(building: &BuildingMap, world_name: &str) -> String {
+    let mut sdf = format!(
+        "<?xml version=\"1.0\"?>\n<sdf version=\"1.7\">\n  <world name=\"{}\">\n",
+        escape(world_name));
+    sdf += "    <light type=\"directional\" name=\"sun\">\n      \
+                <cast_shadows>true</cast_shadows>\n      \
+                <pose>0 0 10 0 0 0</pose>\n      \
+                <diffuse>1 1 1 1</diffuse>\n      \
+                <specular>0.2 0.2 0.2 1</specular>\n      \
+                <direction>-0.5 0.1 -0.9</direction>\n    \
+              </light>\n";
+
+    let mut names = BTreeSet::new();
+    for level in building.levels.values() {
+        names.insert(format!("{}_{}", world_name, level.name));
+        sdf += &format!(
+            "    <include>\n      \
+               <name>{0}_{1}</name>\n      \
+               <uri>model://{0}_{1}</uri>\n      \
+               <pose>0 0 {2} 0 0 0</pose>\n    \
+             </include>\n",
+            escape(world_name), escape(&level.name), level.elevation);
+
+        for (i, door) in level.doors.iter().enumerate() {
+            let name = unique_name(&mut names, &door.name, &format!("{}_door_{}", level.name, i));
+            sdf += &door_model(level, door, &name);
+        }
+
+        let scale = level.scale();
+        for model in &level.models {
+            // Fuel models are named <owner>/<name>, but included by name only
+            let uri = model.model_name.rsplit('/').next().unwrap_or(&model.model_name);
+            let name = unique_name(&mut names, &model.name, uri);
+            sdf += &format!(
+                "    <include>\n      \
+                   <name>{}</name>\n      \
+                   <uri>model://{}</uri>\n      \
+                   <pose>{} {} {} 0 0 {}</pose>\n      \
+                   <static>{}</static>\n    \
+                 </include>\n",
+                escape(&name), escape(uri),
+                model.x * scale, -model.y * scale, level.elevation + model.z, model.yaw,
+                model.static_);
+        }
+    }
+    sdf += "  </world>\n</sdf>\n";
+    sdf
+}
+
+/// `name`, or `fallback` if it is empty, with a number added if it is
+/// already taken: SDF needs every model in a world to have its own name.
+fn unique_name(names: &mut BTreeSet<String>, name: &str, fallback: &str) -> String {
+    let base = if name.is_empty() { fallback } else { name };
+    let mut unique = base.to_string();
+    let mut n = 1;
+    while names.contains(&unique) {
+        unique = format!("{}_{}", base, n);
+        n += 1;
+    }
+    names.insert(unique.clone());
+    unique
+}
+
+/// A door as a model with one link per leaf, each hanging off the world on
+/// a hinge limited to the door's `motion_degrees`, or for sliding doors on
+/// a rail as long as the leaf.
+fn door_model(level: &Level, door: &Door, name: &str) -> String {
+    let (start, end) = match (metres(level, door.start), metres(level, door.end)) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return String::new(),
+    };
+    let mut sdf = format!(
+        "    <model name=\"{}\">\n      <pose>0 0 {} 0 0 0</pose>\n",
+        escape(name), level.elevation);
+
+    let panels = door_panels(door, start, end);
+    let leaves = ["left", "right"];
+    for (panel, leaf) in panels.iter().zip(leaves.iter()) {
+        let yaw = panel.dir[1].atan2(panel.dir[0]);
+        let center = [
+            panel.pivot[0] + panel.dir[0] * panel.length / 2.,
+            panel.pivot[1] + panel.dir[1] * panel.length / 2.,
+        ];
+        let geometry = format!(
+            "<geometry><box><size>{} {} {}</size></box></geometry>",
+            panel.length, DOOR_THICKNESS, DOOR_HEIGHT);
+        sdf += &format!(
+            "      <link name=\"{0}\">\n        \
+                 <pose>{1} {2} {3} 0 0 {4}</pose>\n        \
+                 <inertial><mass>50</mass></inertial>\n        \
+                 <visual name=\"{0}_visual\">{5}</visual>\n        \
+                 <collision name=\"{0}_collision\">{5}</collision>\n      \
+               </link>\n",
+            leaf, center[0], center[1], DOOR_HEIGHT / 2., yaw, geometry);
+
+        // the joint frame is given relative to the leaf, whose x axis runs
+        // along it from the pivot
+        let (joint_type, x, lower, upper) = if door.door_type.is_sliding() {
+            ("prismatic", 0., -panel.length, 0.)
+        } else {
+            ("revolute", -panel.length / 2., panel.swing.min(0.), panel.swing.max(0.))
+        };
+        let axis = if door.door_type.is_sliding() { "1 0 0" } else { "0 0 1" };
+        sdf += &format!(
+            "      <joint name=\"{0}_joint\" type=\"{1}\">\n        \
+                 <parent>world</parent>\n        \
+                 <child>{0}</child>\n        \
+                 <pose>{2} 0 0 0 0 0</pose>\n        \
+                 <axis>\n          \
+                   <xyz>{3}</xyz>\n          \
+                   <limit><lower>{4}</lower><upper>{5}</upper></limit>\n        \
+                 </axis>\n      \
+               </joint>\n",
+            leaf, joint_type, x, axis, lower, upper);
+    }
+
+    let plugin = door.params.get("plugin").and_then(|p| p.as_str()).unwrap_or("normal");
+    if plugin != "none" && !panels.is_empty() {
+        let door_type = match door.door_type {
+            DoorType::Hinged => "SwingDoor",
+            DoorType::DoubleHinged => "DoubleSwingDoor",
+            DoorType::Sliding => "SlidingDoor",
+            DoorType::DoubleSliding => "DoubleSlidingDoor",
+        };
+        let right = if panels.len() > 1 { "right_joint" } else { "empty_joint" };
+        sdf += &format!(
+            "      <plugin name=\"door\" filename=\"libdoor.so\">\n        \
+                 <v_max_door>0.5</v_max_door>\n        \
+                 <a_max_door>0.3</a_max_door>\n        \
+                 <a_nom_door>0.15</a_nom_door>\n        \
+                 <dx_min_door>0.01</dx_min_door>\n        \
+                 <f_max_door>500.0</f_max_door>\n        \
+                 <door name=\"{}\" type=\"{}\" left_joint_name=\"left_joint\" right_joint_name=\"{}\"/>\n      \
+               </plugin>\n",
+            escape(name), door_type, right);
+    }
+    sdf += "    </model>\n";
+    sdf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILDING: &str = "\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices: [[0, 0, 0, ''], [100, 0, 0, ''], [0, 100, 0, ''], [100, 100, 0, ''], [0, 200, 0, ''], [100, 200, 0, '']]
+    doors:
+      - [0, 1, {name: [1, door], type: [1, hinged], motion_degrees: [3, 45], motion_direction: [2, 1]}]
+      - [2, 3, {name: [1, door], type: [1, sliding]}]
+      - [4, 5, {type: [1, hinged]}]
+    models:
+      - {model_name: OpenRobotics/Chair, name: chair, x: 10, y: 20}
+      - {model_name: OpenRobotics/Chair, name: chair, x: 30, y: 40}
+      - {model_name: Table, x: 50, y: 60}
+";
+
+    fn world_sdf() -> String {
+        let building = BuildingMap::from_yaml(BUILDING).unwrap();
+        world(&building, "test")
+    }
+
+    /// The text between each `open` and the following `close`.
+    fn between<'a>(text: &'a str, open: &str, close: &str) -> Vec<&'a str> {
+        text.split(open).skip(1).map(|rest| &rest[..rest.find(close).unwrap()]).collect()
+    }
+
+    #[test]
+    fn every_model_has_its_own_name() {
+        let sdf = world_sdf();
+        let mut names = between(&sdf, "<include>\n      <name>", "</name>");
+        names.extend(between(&sdf, "<model name=\"", "\""));
+        assert_eq!(names, vec!["test_L1", "chair", "chair_1", "Table", "door", "door_1", "L1_door_2"]);
+    }
+
+    #[test]
+    fn door_joints_are_limited_to_their_motion() {
+        let sdf = world_sdf();
+        let joints = between(&sdf, "<joint ", "</joint>");
+        assert_eq!(joints.len(), 3);
+        let swing = 45f64.to_radians();
+        assert!(joints[0].contains("type=\"revolute\""));
+        assert!(joints[0].contains(&format!("<lower>0</lower><upper>{}</upper>", swing))
+            || joints[0].contains(&format!("<lower>{}</lower><upper>0</upper>", -swing)));
+        assert!(joints[1].contains("type=\"prismatic\""));
+        assert!(joints[1].contains("<lower>-1</lower><upper>0</upper>"));
+        let default_swing = 90f64.to_radians();
+        assert!(joints[2].contains(&format!("{}</", default_swing)));
+    }
+
+    #[test]
+    fn models_are_included_by_name() {
+        let sdf = world_sdf();
+        let uris = between(&sdf, "<uri>", "</uri>");
+        assert_eq!(uris, vec!["model://test_L1", "model://Chair", "model://Chair", "model://Table"]);
+        assert!(sdf.contains("<pose>0.1 -0.2 0 0 0 0</pose>"));
+    }
+}
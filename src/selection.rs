@@ -0,0 +1,341 @@
+use bevy::{
+    ecs::prelude::*,
+    input::{keyboard::KeyCode, mouse::MouseButton, Input},
+    math::{Vec2, Vec3},
+    pbr2::StandardMaterial,
+    prelude::{App, GlobalTransform, Handle, Plugin, Transform},
+    window::Windows,
+};
+use bevy_egui::EguiContext;
+
+use crate::edit::{Edit, Element};
+use crate::site_map::{ElementId, ElementKind, Level, LevelTransform, SiteHandles, SiteMap, LANE_WIDTH};
+use crate::triangulate;
+use crate::tools::{EditorTools, Tool};
+use crate::supercamera::{CursorPoint, FlexibleProjection, MouseCaptured, ProjectionMode, SUPERCAMERA_MOTION};
+
+/// How close, in metres, a click has to be to a vertex to pick it.
+const PICK_RADIUS: f32 = 0.3;
+
+/// How close, in metres, a click has to be to the line of a door, wall or
+/// measurement to pick it.
+const EDGE_PICK_RADIUS: f32 = 0.15;
+
+/// The element the user is working on, if any.
+#[derive(Default)]
+pub struct Selection {
+    pub selected: Option<ElementId>,
+    /// While the selected vertex follows the mouse: where the cursor last
+    /// met the vertex's level, in world coordinates.
+    drag_point: Option<Vec2>,
+}
+
+/// Distance along the ray to where it first enters the sphere, if it does.
+fn ray_hits_sphere(origin: Vec3, dir: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+    let to_center = center - origin;
+    let along = to_center.dot(dir);
+    let miss_sq = to_center.length_squared() - along * along;
+    if miss_sq > radius * radius {
+        return None;
+    }
+    let t = along - (radius * radius - miss_sq).sqrt();
+    if t < 0. {
+        None
+    } else {
+        Some(t)
+    }
+}
+
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let span = b - a;
+    let along = ((p - a).dot(span) / span.length_squared().max(1e-9)).clamp(0., 1.);
+    p.distance(a + span * along)
+}
+
+/// The element of a level at `p`, other than a vertex: a lane, door, wall
+/// or measurement close enough to its line, in that order, or failing
+/// those the floor it is on.
+fn pick_in_level(name: &str, level: &Level, xf: &LevelTransform, p: Vec2) -> Option<ElementId> {
+    let position = |i: usize| level.vertices.get(i).map(|v| xf.to_world(v.x, v.y));
+    let near = |start: usize, end: usize, reach: f32| -> bool {
+        match (position(start), position(end)) {
+            (Some(a), Some(b)) => distance_to_segment(p, a, b) <= reach,
+            _ => false,
+        }
+    };
+    let id = |kind: ElementKind, i: usize| Some(ElementId::new(name, kind, i));
+
+    if let Some(i) = level.lanes.iter().position(|l| near(l.start, l.end, LANE_WIDTH / 2.)) {
+        return id(ElementKind::Lane, i);
+    }
+    if let Some(i) = level.doors.iter().position(|d| near(d.start, d.end, EDGE_PICK_RADIUS)) {
+        return id(ElementKind::Door, i);
+    }
+    if let Some(i) = level.walls.iter().position(|w| near(w.start, w.end, EDGE_PICK_RADIUS)) {
+        return id(ElementKind::Wall, i);
+    }
+    if let Some(i) = level.measurements.iter().position(|m| near(m.start, m.end, EDGE_PICK_RADIUS)) {
+        return id(ElementKind::Measurement, i);
+    }
+    let point = [p.x as f64, p.y as f64];
+    level.floors.iter()
+        .position(|floor| {
+            let outline: Vec<[f64; 2]> = floor.vertices.iter()
+                .filter_map(|&i| position(i))
+                .map(|v| [v.x as f64, v.y as f64])
+                .collect();
+            triangulate::contains(&outline, point)
+        })
+        .and_then(|i| id(ElementKind::Floor, i))
+}
+
+/// The element under a ray, other than a vertex, on the nearest level
+/// where there is one, with the distance along the ray to it.
+fn pick_on_level(sm: &SiteMap, origin: Vec3, dir: Vec3) -> Option<(f32, ElementId)> {
+    if dir.z.abs() < 1e-6 {
+        return None;
+    }
+    let mut best: Option<(f32, ElementId)> = None;
+    for (name, level) in sm.levels() {
+        let xf = match sm.level_transform(name) {
+            Some(xf) => xf,
+            None => continue,
+        };
+        let t = (xf.elevation - origin.z) / dir.z;
+        if t < 0. || matches!(&best, Some((best_t, _)) if *best_t <= t) {
+            continue;
+        }
+        let p = origin + dir * t;
+        if let Some(id) = pick_in_level(name, level, xf, Vec2::new(p.x, p.y)) {
+            best = Some((t, id));
+        }
+    }
+    best
+}
+
+/// Left click picks the nearest vertex under the cursor, or failing that
+/// the lane, door, wall, measurement or floor under it, or clears the
+/// selection. In the 2D view, holding the button drags the vertex across
+/// its level, moving the lanes and walls attached to it along with it.
+fn select_and_drag(
+    mut sm: ResMut<SiteMap>,
+    mut selection: ResMut<Selection>,
+    mut mouse_captured: ResMut<MouseCaptured>,
+    tools: Res<EditorTools>,
+    egui_context: Res<EguiContext>,
+    windows: Res<Windows>,
+    input_mouse: Res<Input<MouseButton>>,
+    cameras: Query<(&GlobalTransform, &FlexibleProjection)>,
+    mut elements: Query<(&ElementId, &GlobalTransform, &mut Transform)>,
+) {
+    let button = MouseButton::Left;
+    if input_mouse.just_released(button) && selection.drag_point.is_some() {
+        selection.drag_point = None;
+        mouse_captured.0 = false;
+        // doors and floors only follow the vertex once it is dropped
+        sm.end_gesture();
+    }
+
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let (camera_transform, projection) = cameras.single();
+    let (origin, dir) = projection.screen_to_ray(camera_transform, window_size, cursor);
+    let on_level = |id: &ElementId| -> Option<Vec2> {
+        let elevation = sm.level_transform(&id.level)?.elevation;
+        if dir.z.abs() < 1e-6 {
+            return None;
+        }
+        let p = origin + dir * ((elevation - origin.z) / dir.z);
+        Some(Vec2::new(p.x, p.y))
+    };
+
+    if tools.tool != Tool::Select {
+        return;
+    }
+    if input_mouse.just_pressed(button) && !egui_context.ctx().wants_pointer_input() {
+        let picked = elements.iter()
+            .filter(|(id, _, _)| id.kind == ElementKind::Vertex)
+            .filter_map(|(id, transform, _)| {
+                ray_hits_sphere(origin, dir, transform.translation, PICK_RADIUS).map(|t| (t, id))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, id)| id.clone())
+            .or_else(|| pick_on_level(&sm, origin, dir).map(|(_, id)| id));
+        selection.drag_point = match &picked {
+            Some(id) if id.kind == ElementKind::Vertex
+                && projection.mode == ProjectionMode::Orthographic => on_level(id),
+            _ => None,
+        };
+        mouse_captured.0 = selection.drag_point.is_some();
+        selection.selected = picked;
+    }
+
+    if !input_mouse.pressed(button) {
+        return;
+    }
+    let (id, last_point) = match (&selection.selected, selection.drag_point) {
+        (Some(id), Some(last_point)) => (id.clone(), last_point),
+        _ => return,
+    };
+    let point = match on_level(&id) {
+        Some(point) if point != last_point => point,
+        _ => return,
+    };
+    selection.drag_point = Some(point);
+    let xf = match sm.level_transform(&id.level) {
+        Some(xf) => *xf,
+        None => return,
+    };
+    let from = match sm.levels().get(&id.level).and_then(|l| l.vertices.get(id.index)) {
+        Some(v) => (v.x, v.y),
+        None => return,
+    };
+    // move by the cursor's motion, so the vertex does not jump to the cursor
+    let to = xf.to_drawing(xf.to_world(from.0, from.1) + point - last_point);
+    sm.apply_gesture_step(Edit::MoveVertex {
+        level: id.level.clone(),
+        index: id.index,
+        from,
+        to,
+    });
+
+    let level = &sm.levels()[&id.level];
+    let attached = |e: &ElementId| -> bool {
+        if e.level != id.level {
+            return false;
+        }
+        let edge = match e.kind {
+            ElementKind::Vertex => return e.index == id.index,
+            ElementKind::Lane => level.lanes.get(e.index),
+            ElementKind::Wall => level.walls.get(e.index),
+            ElementKind::Door | ElementKind::Measurement | ElementKind::Floor => None,
+        };
+        matches!(edge, Some(edge) if edge.start == id.index || edge.end == id.index)
+    };
+    for (e, _, mut transform) in elements.iter_mut() {
+        if attached(e) {
+            if let Some(new_transform) = sm.element_transform(e) {
+                *transform = new_transform;
+            }
+        }
+    }
+}
+
+/// Finds the wall or floor under the cursor in the 3D view, for the camera
+/// to zoom toward and orbit around.
+fn pick_cursor_point(
+    sm: Res<SiteMap>,
+    windows: Res<Windows>,
+    cameras: Query<(&GlobalTransform, &FlexibleProjection)>,
+    mut cursor_point: ResMut<CursorPoint>,
+) {
+    cursor_point.0 = None;
+    let (camera_transform, projection) = cameras.single();
+    if projection.mode != ProjectionMode::Perspective {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let (origin, dir) = projection.screen_to_ray(camera_transform, window_size, cursor);
+    cursor_point.0 = sm.raycast(origin, dir).map(|t| origin + dir * t);
+}
+
+/// Delete removes the selected element, and a vertex along with everything
+/// attached to it.
+fn delete_selection(
+    mut sm: ResMut<SiteMap>,
+    mut selection: ResMut<Selection>,
+    keyboard_input: Res<Input<KeyCode>>,
+    egui_context: Res<EguiContext>,
+) {
+    if !keyboard_input.just_pressed(KeyCode::Delete)
+        || egui_context.ctx().wants_keyboard_input()
+        || selection.drag_point.is_some()
+    {
+        return;
+    }
+    let id = match &selection.selected {
+        Some(id) => id.clone(),
+        None => return,
+    };
+    let level = match sm.levels().get(&id.level) {
+        Some(level) => level,
+        None => return,
+    };
+    let edit = match id.kind {
+        ElementKind::Vertex if id.index < level.vertices.len() => {
+            Edit::delete_vertex(level, id.index)
+        }
+        kind => match Element::get(level, kind, id.index) {
+            Some(element) => Edit::Remove {
+                level: id.level.clone(),
+                index: id.index,
+                element,
+            },
+            None => return,
+        },
+    };
+    sm.apply(edit);
+    selection.selected = None;
+}
+
+fn highlight_selection(
+    sm: Res<SiteMap>,
+    selection: Res<Selection>,
+    handles: Res<SiteHandles>,
+    mut elements: Query<(&ElementId, &mut Handle<StandardMaterial>)>,
+) {
+    for (id, mut material) in elements.iter_mut() {
+        let selected = selection.selected.as_ref() == Some(id);
+        let wanted = match id.kind {
+            ElementKind::Vertex if selected => &handles.vertex_selected_material,
+            ElementKind::Vertex => {
+                match sm.levels().get(&id.level).and_then(|l| l.vertices.get(id.index)) {
+                    Some(v) => handles.material_for_vertex(v),
+                    None => continue,
+                }
+            }
+            ElementKind::Lane if selected => &handles.lane_selected_material,
+            ElementKind::Lane => {
+                match sm.levels().get(&id.level).and_then(|l| l.lanes.get(id.index)) {
+                    Some(lane) => handles.lane_material(lane.graph_idx),
+                    None => continue,
+                }
+            }
+            ElementKind::Wall | ElementKind::Door if selected => &handles.vertex_selected_material,
+            ElementKind::Wall => &handles.wall_material,
+            ElementKind::Door => &handles.door_material,
+            _ => continue,
+        };
+        if *material != *wanted {
+            *material = wanted.clone();
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Selection>()
+           .add_system(select_and_drag.before(SUPERCAMERA_MOTION))
+           .add_system(pick_cursor_point.before(SUPERCAMERA_MOTION))
+           .add_system(delete_selection)
+           .add_system(highlight_selection);
+    }
+}
@@ -2,47 +2,62 @@ use bevy::{
     ecs::prelude::*,
     math::{Quat, Vec2, Vec3},
     pbr2::{PbrBundle, StandardMaterial},
-    prelude::{App, Assets, AssetServer, Plugin, Transform},
+    prelude::{
+        App, AssetEvent, Assets, AssetServer, BuildChildren, DespawnRecursiveExt, GlobalTransform,
+        Handle, Plugin, Transform,
+    },
     render2::{
         color::Color,
-        mesh::{shape, Mesh},
+        mesh::{shape, Indices, Mesh},
+        render_resource::{AddressMode, PrimitiveTopology},
+        texture::Image,
+        view::Visibility,
     },
+    scene::{Scene, SpawnSceneAsChildCommands},
+    window::Windows,
 };
+use bevy_egui::{egui, EguiContext};
 
 use std::{
+    collections::{BTreeMap, BTreeSet},
     env,
-    fs::{File, metadata},
+    fs,
+    path::{Path, PathBuf},
 };
 
-use serde_yaml;
+pub use crate::building_map::{
+    BuildingMap, Door, DoorType, ElementId, ElementKind, Lane, Level, Lift, LiftDoor, MotionAxis,
+    SiteMapError, Vertex, Wall,
+};
+use crate::cli::Command;
+use crate::edit::{Edit, EditHistory};
+use crate::geometry::{self, door_panels, DOOR_HEIGHT, DOOR_THICKNESS, WALL_HEIGHT, WALL_THICKNESS};
+use crate::supercamera::{FlexibleProjection, ProjectionMode};
+use crate::triangulate;
 
 // todo: use asset-server or something more sophisticated eventually.
 // for now, just hack it up and toss the office-demo YAML into a big string
 use crate::demo_world::demo_office;
 
 
-pub struct Vertex {
-    x: f64,
-    y: f64,
-    name: String,
-}
-
-pub struct Lane {
-    start: usize,
-    end: usize,
-}
-
-pub struct Wall {
-    start: usize,
-    end: usize,
-}
-
 pub struct SiteMap {
     filename: String,
     site_name: String,
-    vertices: Vec<Vertex>,
-    lanes: Vec<Lane>,
-    walls: Vec<Wall>,
+    levels: BTreeMap<String, Level>,
+    lifts: BTreeMap<String, Lift>,
+    crowd_sim: Option<serde_yaml::Value>,
+    /// The top-level keys of the file, so that saving adds no new sections.
+    loaded_keys: BTreeSet<String>,
+    history: EditHistory,
+    /// Fixed when the map is loaded, so that editing vertices does not
+    /// shift the whole world around.
+    transforms: BTreeMap<String, LevelTransform>,
+    respawn: bool,
+    /// Counts every change to the map, so that anything derived from it
+    /// knows when to recompute.
+    revision: u64,
+    /// Counts loads only, so that views can be reset for each new map.
+    loads: u64,
 }
 
 impl Default for SiteMap {
@@ -50,111 +65,601 @@ impl Default for SiteMap {
         SiteMap {
             filename: String::new(),
             site_name: String::new(),
-            vertices: Vec::new(),
-            lanes: Vec::new(),
-            walls: Vec::new(),
+            levels: BTreeMap::new(),
+            lifts: BTreeMap::new(),
+            crowd_sim: None,
+            loaded_keys: BTreeSet::new(),
+            history: EditHistory::default(),
+            transforms: BTreeMap::new(),
+            respawn: false,
+            revision: 0,
+            loads: 0,
         }
     }
 }
 
+/// Holds the most recent load or save failure, and what the last export
+/// wrote, so the UI can show them.
+#[derive(Default)]
+pub struct SiteMapStatus {
+    pub error: Option<String>,
+    pub message: Option<String>,
+}
+
 impl SiteMap {
-    pub fn load(&mut self, filename: String) {
-        println!("SiteMap loading file: [{}]", filename); //{} = {:?}", args.len(), args);
+    pub fn load(&mut self, filename: String) -> Result<(), SiteMapError> {
+        println!("SiteMap loading file: [{}]", filename);
+        let text = fs::read_to_string(&filename).map_err(|e| SiteMapError::Io {
+            filename: filename.clone(),
+            source: e,
+        })?;
+        self.load_yaml(&text)?;
         self.filename = filename;
-        if !metadata(&self.filename).is_ok() {
-            println!("could not open [{}]", &self.filename);
-            return;
-        }
-        let file = File::open(&self.filename).expect("Could not open file");
-        let doc: serde_yaml::Value = serde_yaml::from_reader(file).ok().unwrap();
-        self.load_yaml(doc);
+        Ok(())
     }
 
-    pub fn load_demo(
-        &mut self,
-    ) {
+    pub fn load_demo(&mut self) -> Result<(), SiteMapError> {
         // todo: use asset-server or something more sophisticated eventually.
         // for now, just hack it up and toss the office-demo YAML into a big string
-        let result: serde_yaml::Result<serde_yaml::Value> = serde_yaml::from_str(&demo_office());
-        if result.is_err() {
-            println!("serde threw an error: {:?}", result.err());
-        }
-        else {
-            let doc: serde_yaml::Value = serde_yaml::from_str(&demo_office()).ok().unwrap();
-            self.load_yaml(doc);
-        }
-    }
-
-    pub fn load_yaml(&mut self, doc: serde_yaml::Value) {
-        self.site_name = doc["name"].as_str().unwrap().to_string();
-        for (k, level_yaml) in doc["levels"].as_mapping().unwrap().iter() { //.iter() {
-            println!("level name: [{}]", k.as_str().unwrap());
-            for vertex_yaml in level_yaml["vertices"].as_sequence().unwrap() {
-                let data = vertex_yaml.as_sequence().unwrap();
-                let x = data[0].as_f64().unwrap();
-                let y = data[1].as_f64().unwrap();
-                let name = if data.len() > 3 { data[3].as_str().unwrap().to_string() } else { String::new() };
-                let v = Vertex {
-                    x: x,
-                    y: -y,
-                    name: name
-                };
-                self.vertices.push(v);
+        self.load_yaml(&demo_office())?;
+        self.filename = String::new();
+        Ok(())
+    }
+
+    /// Parses a whole `.building.yaml` document. On failure the current
+    /// contents of the map are left untouched.
+    pub fn load_yaml(&mut self, text: &str) -> Result<(), SiteMapError> {
+        let building = BuildingMap::from_yaml(text)?;
+        for level_name in building.levels.keys() {
+            println!("level name: [{}]", level_name);
+        }
+        self.site_name = building.name;
+        self.levels = building.levels;
+        self.lifts = building.lifts;
+        self.crowd_sim = building.crowd_sim;
+        self.loaded_keys = building.loaded_keys;
+        self.transforms = self.level_transforms();
+        self.history.clear();
+        self.respawn = true;
+        self.revision += 1;
+        self.loads += 1;
+        Ok(())
+    }
+
+    pub fn levels(&self) -> &BTreeMap<String, Level> {
+        &self.levels
+    }
+
+    pub fn lifts(&self) -> &BTreeMap<String, Lift> {
+        &self.lifts
+    }
+
+    /// Makes a change to the map and records it for undo. The map's
+    /// entities are rebuilt on the next frame.
+    pub fn apply(&mut self, edit: Edit) {
+        edit.apply(&mut self.levels);
+        self.history.push(edit);
+        self.recalibrate();
+        self.respawn = true;
+        self.revision += 1;
+    }
+
+    /// Like `apply`, for one step of a continuous gesture such as a drag:
+    /// the steps are undone together, up to the next `end_gesture`. The
+    /// caller keeps the affected entities up to date while the gesture lasts.
+    pub fn apply_gesture_step(&mut self, edit: Edit) {
+        edit.apply(&mut self.levels);
+        self.history.push_coalesced(edit);
+        self.revision += 1;
+    }
+
+    pub fn end_gesture(&mut self) {
+        if self.history.end_coalescing() {
+            self.recalibrate();
+            self.respawn = true;
+        }
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(edit) = self.history.undo() {
+            edit.apply(&mut self.levels);
+            self.recalibrate();
+            self.respawn = true;
+            self.revision += 1;
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(edit) = self.history.redo() {
+            edit.apply(&mut self.levels);
+            self.recalibrate();
+            self.respawn = true;
+            self.revision += 1;
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn loads(&self) -> u64 {
+        self.loads
+    }
+
+    /// The corners of the box around every level's vertices, from the
+    /// lowest floor to the top of the highest walls. None for an empty map.
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let mut points = Vec::new();
+        for (name, level) in &self.levels {
+            let xf = &self.transforms[name];
+            for v in &level.vertices {
+                let p = xf.to_world(v.x, v.y);
+                points.push(Vec3::new(p.x, p.y, xf.elevation));
+                points.push(Vec3::new(p.x, p.y, xf.elevation + WALL_HEIGHT as f32));
             }
-            for lane_yaml in level_yaml["lanes"].as_sequence().unwrap() {
-                let data = lane_yaml.as_sequence().unwrap();
-                let start = data[0].as_u64().unwrap();
-                let end = data[1].as_u64().unwrap();
-                let lane = Lane {
-                    start: start as usize,
-                    end: end as usize
+        }
+        bounding_box(points)
+    }
+
+    /// The corners of the box around one element: a vertex, the ends of a
+    /// lane, wall, door or measurement, or a floor's outline. None for
+    /// dangling indices.
+    pub fn element_bounds(&self, id: &ElementId) -> Option<(Vec3, Vec3)> {
+        let level = self.levels.get(&id.level)?;
+        let xf = self.transforms.get(&id.level)?;
+        let indices = match id.kind {
+            ElementKind::Vertex => vec![id.index],
+            ElementKind::Lane => level.lanes.get(id.index).map(|l| vec![l.start, l.end])?,
+            ElementKind::Wall => level.walls.get(id.index).map(|w| vec![w.start, w.end])?,
+            ElementKind::Door => level.doors.get(id.index).map(|d| vec![d.start, d.end])?,
+            ElementKind::Measurement => level.measurements.get(id.index).map(|m| vec![m.start, m.end])?,
+            ElementKind::Floor => level.floors.get(id.index).map(|f| f.vertices.clone())?,
+        };
+        let mut points = Vec::new();
+        for i in indices {
+            let v = level.vertices.get(i)?;
+            let p = xf.to_world(v.x, v.y);
+            points.push(Vec3::new(p.x, p.y, xf.elevation));
+        }
+        bounding_box(points)
+    }
+
+    /// Distance along a ray (with `dir` of unit length) to where it first
+    /// meets a wall or a floor, if it does.
+    pub fn raycast(&self, origin: Vec3, dir: Vec3) -> Option<f32> {
+        let mut hits = Vec::new();
+        for (name, level) in &self.levels {
+            let xf = &self.transforms[name];
+            let position = |i: usize| level.vertices.get(i).map(|v| xf.to_world(v.x, v.y));
+            for wall in &level.walls {
+                let (p1, p2) = match (position(wall.start), position(wall.end)) {
+                    (Some(p1), Some(p2)) => (p1, p2),
+                    _ => continue,
                 };
-                self.lanes.push(lane);
+                let center = (p1 + p2) / 2.;
+                let span = p2 - p1;
+                let half = Vec3::new(span.length() / 2., WALL_THICKNESS as f32 / 2., WALL_HEIGHT as f32 / 2.);
+                let rotation = Quat::from_rotation_z(-span.y.atan2(span.x));
+                let local_origin = rotation * (origin - Vec3::new(center.x, center.y, xf.elevation + half.z));
+                hits.extend(ray_hits_box(local_origin, rotation * dir, half));
             }
-            let walls_yaml = level_yaml["walls"].as_sequence();
-            if walls_yaml.is_some() {
-                for wall_yaml in walls_yaml.unwrap() {
-                    let data = wall_yaml.as_sequence().unwrap();
-                    let start = data[0].as_u64().unwrap();
-                    let end = data[1].as_u64().unwrap();
-                    let wall = Wall {
-                        start: start as usize,
-                        end: end as usize
-                    };
-                    self.walls.push(wall);
-                }
+
+            if dir.z.abs() < 1e-6 {
+                continue;
+            }
+            let t = (xf.elevation - origin.z) / dir.z;
+            if t < 0. {
+                continue;
+            }
+            let p = origin + dir * t;
+            let p = [p.x as f64, p.y as f64];
+            let polygon = |indices: &[usize]| -> Vec<[f64; 2]> {
+                indices.iter().filter_map(|&i| position(i)).map(|p| [p.x as f64, p.y as f64]).collect()
+            };
+            let in_hole = level.holes.iter().any(|h| triangulate::contains(&polygon(&h.vertices), p));
+            if !in_hole && level.floors.iter().any(|f| triangulate::contains(&polygon(&f.vertices), p)) {
+                hits.push(t);
             }
         }
+        hits.into_iter().reduce(f32::min)
+    }
+
+    pub fn level_transform(&self, level: &str) -> Option<&LevelTransform> {
+        self.transforms.get(level)
+    }
+
+    pub fn filename(&self) -> &str {
+        &self.filename
+    }
+
+    /// Writes the map in the legacy `.building.yaml` format and makes
+    /// `filename` the map's current file.
+    pub fn save(&mut self, filename: String) -> Result<(), SiteMapError> {
+        println!("SiteMap saving file: [{}]", filename);
+        let text = self.to_building_map().to_yaml()?;
+        fs::write(&filename, text).map_err(|e| SiteMapError::Io {
+            filename: filename.clone(),
+            source: e,
+        })?;
+        self.filename = filename;
+        Ok(())
+    }
+
+    pub fn to_building_map(&self) -> BuildingMap {
+        BuildingMap {
+            crowd_sim: self.crowd_sim.clone(),
+            levels: self.levels.clone(),
+            lifts: self.lifts.clone(),
+            name: self.site_name.clone(),
+            loaded_keys: self.loaded_keys.clone(),
+        }
     }
 
     fn _print(&self) {
         println!("site name: [{}]", &self.site_name);
-        println!("vertices:");
-        for v in &self.vertices {
-            println!("{} {} {}", v.name, v.x, v.y);
+        for level in self.levels.values() {
+            println!("level [{}] vertices:", level.name);
+            for v in &level.vertices {
+                println!("{} {} {}", v.name, v.x, v.y);
+            }
         }
     }
 
-    pub fn spawn(
-        &self,
-        mut commands: Commands,
-        mut meshes: ResMut<Assets<Mesh>>,
-        mut materials: ResMut<Assets<StandardMaterial>>,
-        _asset_server: Res<AssetServer>,
-    ) {
+    /// Refits each level's scale to its measurements after an edit, as the
+    /// exports do. The shared offset stays where it was loaded, so that the
+    /// view does not jump.
+    fn recalibrate(&mut self) {
+        for (name, level) in &self.levels {
+            if let Some(xf) = self.transforms.get_mut(name) {
+                xf.scale = level.scale();
+            }
+        }
+    }
+
+    /// Where each level's drawing coordinates end up in the world. Each
+    /// level has its own scale, calibrated from its measurements, but all
+    /// levels share one offset so that they stay stacked on each other.
+    fn level_transforms(&self) -> BTreeMap<String, LevelTransform> {
+        let scales: BTreeMap<&String, f64> = self.levels.iter()
+            .map(|(name, level)| (name, level.scale()))
+            .collect();
+
         let mut ofs_x = 0.0;
         let mut ofs_y = 0.0;
-        let scale = 1.0 / 100.0;
         let mut num_v = 0;
-        for v in &self.vertices {
-            ofs_x += v.x;
-            ofs_y += v.y;
-            num_v += 1;
+        for (name, level) in &self.levels {
+            for v in &level.vertices {
+                ofs_x += v.x * scales[name];
+                ofs_y -= v.y * scales[name];
+                num_v += 1;
+            }
+        }
+        if num_v > 0 {
+            ofs_x /= num_v as f64;
+            ofs_y /= num_v as f64;
+        }
+
+        self.levels.iter().map(|(name, level)| {
+            (name.clone(), LevelTransform {
+                ofs_x,
+                ofs_y,
+                scale: scales[name],
+                elevation: level.elevation as f32,
+            })
+        }).collect()
+    }
+
+    pub fn spawn(
+        &self,
+        commands: &mut Commands,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+        asset_server: &AssetServer,
+        images: &Assets<Image>,
+        handles: &SiteHandles,
+        model_settings: &ModelSettings,
+        texture_settings: &TextureSettings,
+    ) {
+        let transforms = &self.transforms;
+        for (name, level) in &self.levels {
+            spawn_level(self, level, &transforms[name], handles, commands, meshes);
+            spawn_floors(level, &transforms[name], commands, meshes, materials, asset_server, texture_settings);
+            spawn_models(level, &transforms[name], handles, commands, asset_server, model_settings);
+        }
+        for lift in self.lifts.values() {
+            self.spawn_lift(lift, transforms, handles, commands, meshes);
+        }
+        for (name, level) in &self.levels {
+            self.spawn_drawing(level, &transforms[name], commands, meshes, materials, asset_server, images);
+        }
+    }
+
+    /// Where the entity of a vertex, lane or wall belongs, given the current
+    /// vertex positions. None for other kinds or dangling indices.
+    pub fn element_transform(&self, id: &ElementId) -> Option<Transform> {
+        let level = self.levels.get(&id.level)?;
+        let xf = self.transforms.get(&id.level)?;
+        let position = |index: usize| -> Option<Vec2> {
+            level.vertices.get(index).map(|v| xf.to_world(v.x, v.y))
+        };
+        match id.kind {
+            ElementKind::Vertex => {
+                let p = position(id.index)?;
+                Some(Transform {
+                    translation: Vec3::new(p.x, p.y, xf.elevation),
+                    rotation: Quat::from_rotation_x(1.57),
+                    ..Default::default()
+                })
+            }
+            ElementKind::Lane => {
+                let lane = level.lanes.get(id.index)?;
+                // stagger the lanes a little to avoid flicker where they overlap
+                let z = xf.elevation + 0.01 + 0.001 * id.index as f32;
+                Some(edge_transform(position(lane.start)?, position(lane.end)?, z, LANE_WIDTH, 1.))
+            }
+            ElementKind::Wall => {
+                let wall = level.walls.get(id.index)?;
+                Some(edge_transform(
+                    position(wall.start)?,
+                    position(wall.end)?,
+                    xf.elevation + WALL_HEIGHT as f32 / 2.,
+                    WALL_THICKNESS as f32,
+                    WALL_HEIGHT as f32))
+            }
+            ElementKind::Door | ElementKind::Measurement | ElementKind::Floor => None,
+        }
+    }
+
+    /// Drawings are looked up next to the building file, so there is nothing
+    /// to show for the built-in demo.
+    fn spawn_drawing(
+        &self,
+        level: &Level,
+        xf: &LevelTransform,
+        commands: &mut Commands,
+        meshes: &mut ResMut<Assets<Mesh>>,
+        materials: &mut ResMut<Assets<StandardMaterial>>,
+        asset_server: &AssetServer,
+        images: &Assets<Image>,
+    ) {
+        let drawing = match &level.drawing {
+            Some(drawing) if !drawing.filename.is_empty() && !self.filename.is_empty() => drawing,
+            _ => return,
+        };
+        let path = Path::new(&self.filename)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&drawing.filename);
+        if !cfg!(target_arch = "wasm32") && !path.exists() {
+            println!("drawing [{}] not found", path.display());
+            return;
+        }
+        let path = match path.canonicalize() {
+            Ok(path) => path,
+            Err(_) => path,
+        };
+
+        let image: Handle<Image> = asset_server.load(path.as_path());
+        let material = materials.add(StandardMaterial {
+            base_color: Color::rgba(1.0, 1.0, 1.0, 0.7),
+            base_color_texture: Some(image.clone()),
+            unlit: true,
+            ..Default::default()
+        });
+        // an image that is still loading is sized by fit_drawings() once it
+        // arrives; one that was already loaded, as it is when the map is
+        // respawned after an edit, is sized right away
+        let underlay = DrawingUnderlay {
+            size: images.get(&image).map(pixel_size),
+            image,
+            transform: *xf,
+        };
+        let mut transform = Transform {
+            translation: Vec3::new(0., 0., xf.elevation + 0.002),
+            scale: Vec3::ZERO,
+            ..Default::default()
+        };
+        underlay.fit(&mut transform);
+        commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE))),
+            material,
+            transform,
+            ..Default::default()
+        })
+        .insert(underlay)
+        .insert(SiteMapEntity);
+    }
+
+    /// Spawns one cabin-sized section of shaft per served level, so that the
+    /// shaft runs through every floor, plus the doors that open there.
+    fn spawn_lift(
+        &self,
+        lift: &Lift,
+        transforms: &BTreeMap<String, LevelTransform>,
+        handles: &SiteHandles,
+        commands: &mut Commands,
+        meshes: &mut ResMut<Assets<Mesh>>,
+    ) {
+        let xf = match transforms.get(&lift.reference_floor_name).or_else(|| transforms.values().next()) {
+            Some(xf) => xf,
+            None => return,
+        };
+        let center = xf.to_world(lift.x, lift.y);
+        let rotation = Quat::from_rotation_z(lift.yaw as f32);
+        let (width, depth) = (lift.width as f32, lift.depth as f32);
+        let default_height = 2.5 as f32;
+
+        let served = lift.served_levels(&self.levels);
+        for (i, level_name) in served.iter().enumerate() {
+            let elevation = self.levels[level_name].elevation as f32;
+            let height = served.get(i + 1)
+                .map(|next| self.levels[next].elevation as f32 - elevation)
+                .filter(|h| *h > 0.)
+                .unwrap_or(default_height);
+
+            commands.spawn_bundle(PbrBundle {
+                mesh: meshes.add(Mesh::from(shape::Box::new(width, depth, height))),
+                material: handles.lift_material.clone(),
+                transform: Transform {
+                    translation: Vec3::new(center.x, center.y, elevation + height / 2.),
+                    rotation,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .insert(SiteMapEntity);
+
+            let door_names = lift.level_doors.get(level_name).map(|d| d.as_slice()).unwrap_or(&[]);
+            for door in door_names.iter().filter_map(|name| lift.doors.get(name)) {
+                let thickness = 0.05 as f32;
+                let door_height = 0.95 as f32;
+                let offset = rotation * Vec3::new(door.x as f32, door.y as f32, 0.);
+                commands.spawn_bundle(PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Box::new(door.width as f32, thickness, door_height))),
+                    material: handles.door_material.clone(),
+                    transform: Transform {
+                        translation: Vec3::new(
+                            center.x + offset.x,
+                            center.y + offset.y,
+                            elevation + door_height / 2.),
+                        rotation: rotation * Quat::from_rotation_z(door.motion_axis_orientation as f32),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .insert(SiteMapEntity);
+            }
+        }
+    }
+}
+
+/// Maps a level's drawing coordinates (pixels, y pointing down) into the
+/// world frame (metres, y pointing up, z at the level's elevation).
+#[derive(Debug, Clone, Copy)]
+pub struct LevelTransform {
+    /// Offset of the world origin, in metres.
+    pub ofs_x: f64,
+    pub ofs_y: f64,
+    /// Metres per drawing pixel.
+    pub scale: f64,
+    pub elevation: f32,
+}
+
+impl LevelTransform {
+    pub fn to_world(&self, x: f64, y: f64) -> Vec2 {
+        Vec2::new(
+            (x * self.scale - self.ofs_x) as f32,
+            (-y * self.scale - self.ofs_y) as f32,
+        )
+    }
+
+    pub fn to_drawing(&self, p: Vec2) -> (f64, f64) {
+        (
+            (p.x as f64 + self.ofs_x) / self.scale,
+            -(p.y as f64 + self.ofs_y) / self.scale,
+        )
+    }
+}
+
+/// Distance along a ray to where it enters the box of the given half
+/// sizes centred on the origin, or zero if it starts inside.
+fn ray_hits_box(origin: Vec3, dir: Vec3, half: Vec3) -> Option<f32> {
+    let (mut near, mut far) = (0_f32, f32::INFINITY);
+    for axis in 0..3 {
+        if dir[axis].abs() < 1e-9 {
+            if origin[axis].abs() > half[axis] {
+                return None;
+            }
+            continue;
         }
-        ofs_x /= num_v as f64;
-        ofs_y /= num_v as f64;
+        let t1 = (-half[axis] - origin[axis]) / dir[axis];
+        let t2 = (half[axis] - origin[axis]) / dir[axis];
+        near = near.max(t1.min(t2));
+        far = far.min(t1.max(t2));
+        if near > far {
+            return None;
+        }
+    }
+    Some(near)
+}
+
+fn bounding_box(points: Vec<Vec3>) -> Option<(Vec3, Vec3)> {
+    let first = *points.first()?;
+    Some(points.iter().fold((first, first), |(min, max), p| (min.min(*p), max.max(*p))))
+}
+
+pub const LANE_WIDTH: f32 = geometry::LANE_WIDTH as f32;
+
+/// Places a unit-sized lane quad or wall box so that it spans p1 to p2.
+fn edge_transform(p1: Vec2, p2: Vec2, z: f32, width: f32, height: f32) -> Transform {
+    let d = p2 - p1;
+    let center = (p1 + p2) / 2.;
+    Transform {
+        translation: Vec3::new(center.x, center.y, z),
+        rotation: Quat::from_rotation_z(d.y.atan2(d.x)),
+        scale: Vec3::new(d.length(), width, height),
+    }
+}
 
-        let vertex_handle = meshes.add(
+/// Meshes and materials shared between all the elements of one kind.
+pub struct SiteHandles {
+    pub vertex_mesh: Handle<Mesh>,
+    pub vertex_material: Handle<StandardMaterial>,
+    pub vertex_selected_material: Handle<StandardMaterial>,
+    pub charger_material: Handle<StandardMaterial>,
+    pub spawn_material: Handle<StandardMaterial>,
+    pub parking_material: Handle<StandardMaterial>,
+    pub holding_material: Handle<StandardMaterial>,
+    pub workcell_material: Handle<StandardMaterial>,
+    pub lane_mesh: Handle<Mesh>,
+    /// One material per navigation graph, cycled through by `graph_idx`.
+    pub lane_materials: Vec<Handle<StandardMaterial>>,
+    pub lane_selected_material: Handle<StandardMaterial>,
+    pub lane_arrow_material: Handle<StandardMaterial>,
+    pub wall_mesh: Handle<Mesh>,
+    pub wall_material: Handle<StandardMaterial>,
+    pub door_material: Handle<StandardMaterial>,
+    pub door_swing_material: Handle<StandardMaterial>,
+    pub placeholder_mesh: Handle<Mesh>,
+    pub placeholder_material: Handle<StandardMaterial>,
+    pub lift_material: Handle<StandardMaterial>,
+}
+
+impl SiteHandles {
+    /// Vertices are coloured by their most significant waypoint role.
+    pub fn material_for_vertex(&self, v: &Vertex) -> &Handle<StandardMaterial> {
+        if v.is_charger() {
+            &self.charger_material
+        } else if !v.spawn_robot_name().is_empty() {
+            &self.spawn_material
+        } else if v.is_parking_spot() {
+            &self.parking_material
+        } else if v.is_holding_point() {
+            &self.holding_material
+        } else if !v.dropoff_ingestor().is_empty() || !v.pickup_dispenser().is_empty() {
+            &self.workcell_material
+        } else {
+            &self.vertex_material
+        }
+    }
+
+    pub fn lane_material(&self, graph_idx: i64) -> &Handle<StandardMaterial> {
+        &self.lane_materials[graph_idx.rem_euclid(self.lane_materials.len() as i64) as usize]
+    }
+}
+
+impl FromWorld for SiteHandles {
+    fn from_world(world: &mut World) -> Self {
+        let mut meshes = world.get_resource_mut::<Assets<Mesh>>().unwrap();
+        let vertex_mesh = meshes.add(
             Mesh::from(
                 shape::Capsule {
                     radius: 0.25,
@@ -166,108 +671,638 @@ impl SiteMap {
                 }
             )
         );
+        let lane_mesh = meshes.add(Mesh::from(shape::Quad::new(Vec2::ONE)));
+        let wall_mesh = meshes.add(Mesh::from(shape::Box::new(1., 1., 1.)));
+        let placeholder_mesh = meshes.add(Mesh::from(shape::Cube { size: 0.5 }));
+
+        let mut materials = world.get_resource_mut::<Assets<StandardMaterial>>().unwrap();
+        SiteHandles {
+            vertex_mesh,
+            vertex_material: materials.add(Color::rgb(0.4, 0.7, 0.6).into()),
+            vertex_selected_material: materials.add(Color::rgb(1.0, 0.9, 0.2).into()),
+            charger_material: materials.add(Color::rgb(0.2, 0.9, 0.2).into()),
+            spawn_material: materials.add(Color::rgb(0.8, 0.3, 1.0).into()),
+            parking_material: materials.add(Color::rgb(0.2, 0.4, 1.0).into()),
+            holding_material: materials.add(Color::rgb(1.0, 0.5, 0.1).into()),
+            workcell_material: materials.add(Color::rgb(0.1, 0.9, 0.9).into()),
+            lane_mesh,
+            lane_materials: [
+                Color::rgba(1.0, 0.5, 0.3, 0.5),
+                Color::rgba(0.3, 0.8, 0.3, 0.5),
+                Color::rgba(0.3, 0.5, 1.0, 0.5),
+                Color::rgba(0.9, 0.3, 0.8, 0.5),
+                Color::rgba(0.9, 0.9, 0.2, 0.5),
+                Color::rgba(0.2, 0.9, 0.9, 0.5),
+            ].iter().map(|c| materials.add((*c).into())).collect(),
+            lane_selected_material: materials.add(Color::rgba(1.0, 0.9, 0.2, 0.8).into()),
+            lane_arrow_material: materials.add(Color::rgb(0.9, 0.9, 0.9).into()),
+            wall_mesh,
+            wall_material: materials.add(Color::rgb(0.5, 0.5, 1.0).into()),
+            door_material: materials.add(Color::rgb(0.6, 0.4, 0.2).into()),
+            door_swing_material: materials.add(Color::rgba(1.0, 0.8, 0.2, 0.4).into()),
+            placeholder_mesh,
+            placeholder_material: materials.add(Color::rgba(0.8, 0.3, 0.8, 0.6).into()),
+            lift_material: materials.add(Color::rgba(0.3, 0.8, 0.9, 0.3).into()),
+        }
+    }
+}
 
-        let vertex_material_handle = materials.add(Color::rgb(0.4, 0.7, 0.6).into());
+/// Marks every entity spawned from the map, so that they can all be
+/// despawned when the map is rebuilt.
+pub struct SiteMapEntity;
 
-        for v in &self.vertices {
-            commands.spawn_bundle(PbrBundle {
-                mesh: vertex_handle.clone(),
-                material: vertex_material_handle.clone(),
-                transform: Transform {
-                    translation: Vec3::new(
-                        ((v.x - ofs_x) * scale) as f32,
-                        ((v.y - ofs_y) * scale) as f32,
-                        0.0,
-                    ),
-                    rotation: Quat::from_rotation_x(1.57),
-                    ..Default::default()
-                },
-                ..Default::default()
-            });
+/// Marks the flat swing arcs and slide tracks of doors, which are only
+/// shown in the 2D view.
+pub struct DoorSwing;
+
+/// Marks the arrows drawn along one-way lanes, which are only shown in the
+/// 2D view.
+pub struct LaneArrow;
+
+/// Flat chevrons spaced along a one-way lane, pointing from p1 to p2. None
+/// if the lane is too short to show one.
+fn arrow_mesh(p1: Vec2, p2: Vec2) -> Option<Mesh> {
+    let span = p2 - p1;
+    let length = span.length();
+    let (arrow_length, spacing) = (0.3 as f32, 1.0 as f32);
+    if length < arrow_length {
+        return None;
+    }
+    let dir = span / length;
+    let side = dir.perp() * (LANE_WIDTH * 0.3);
+    let count = ((length / spacing) as usize).max(1);
+
+    let mut positions = Vec::new();
+    for k in 0..count {
+        // centre the row of arrows on the lane
+        let along = (length - (count - 1) as f32 * spacing) / 2. + k as f32 * spacing;
+        let tip = p1 + dir * (along + arrow_length / 2.);
+        let back = p1 + dir * (along - arrow_length / 2.);
+        for p in [tip, back + side, back - side] {
+            positions.push([p.x, p.y, 0.]);
         }
+    }
+    let normals = vec![[0., 0., 1.]; positions.len()];
+    let uvs = vec![[0., 0.]; positions.len()];
+    let indices = (0..count as u32).flat_map(|k| [3 * k, 3 * k + 1, 3 * k + 2]).collect();
 
-        let lane_material_handle = materials.add(Color::rgba(1.0, 0.5, 0.3, 0.5).into());
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    Some(mesh)
+}
 
-        let mut z_ofs = 0.01;
-        for lane in &self.lanes {
-            let v1 = &self.vertices[lane.start];
-            let v2 = &self.vertices[lane.end];
-            let v1x = ((v1.x - ofs_x) * scale) as f32;
-            let v1y = ((v1.y - ofs_y) * scale) as f32;
-            let v2x = ((v2.x - ofs_x) * scale) as f32;
-            let v2y = ((v2.y - ofs_y) * scale) as f32;
+/// A flat circular sector around the origin, facing +z.
+fn sector_mesh(radius: f32, start_angle: f32, sweep: f32) -> Mesh {
+    let segments = 16;
+    let mut positions = vec![[0., 0., 0.]];
+    let mut uvs = vec![[0.5, 0.5]];
+    for i in 0..=segments {
+        let a = start_angle + sweep * i as f32 / segments as f32;
+        positions.push([radius * a.cos(), radius * a.sin(), 0.]);
+        uvs.push([0.5 + 0.5 * a.cos(), 0.5 + 0.5 * a.sin()]);
+    }
+    let normals = vec![[0., 0., 1.]; positions.len()];
+    let mut indices = Vec::new();
+    for i in 1..=segments {
+        // keep the winding counter-clockwise whichever way the door swings
+        if sweep >= 0. {
+            indices.extend_from_slice(&[0, i, i + 1]);
+        } else {
+            indices.extend_from_slice(&[0, i + 1, i]);
+        }
+    }
 
-            let dx = v2x - v1x;
-            let dy = v2y - v1y;
-            let length = Vec2::from([dx, dy]).length();
-            let width = 0.5 as f32;
-            let yaw = dy.atan2(dx);
-            let cx = (v1x + v2x) / 2.;
-            let cy = (v1y + v2y) / 2.;
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+fn spawn_level(
+    site_map: &SiteMap,
+    level: &Level,
+    xf: &LevelTransform,
+    handles: &SiteHandles,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+) {
+    let elevation = xf.elevation;
+    let mut spawn_element = |
+        kind: ElementKind,
+        index: usize,
+        mesh: &Handle<Mesh>,
+        material: &Handle<StandardMaterial>,
+    | {
+        let id = ElementId::new(&level.name, kind, index);
+        let transform = match site_map.element_transform(&id) {
+            Some(transform) => transform,
+            None => return,
+        };
+        commands.spawn_bundle(PbrBundle {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            transform,
+            ..Default::default()
+        })
+        .insert(id)
+        .insert(SiteMapEntity);
+    };
+
+    for (i, v) in level.vertices.iter().enumerate() {
+        spawn_element(ElementKind::Vertex, i, &handles.vertex_mesh, handles.material_for_vertex(v));
+    }
+    for (i, lane) in level.lanes.iter().enumerate() {
+        spawn_element(ElementKind::Lane, i, &handles.lane_mesh, handles.lane_material(lane.graph_idx));
+    }
+    for i in 0..level.walls.len() {
+        spawn_element(ElementKind::Wall, i, &handles.wall_mesh, &handles.wall_material);
+    }
+
+    for (i, lane) in level.lanes.iter().enumerate().filter(|(_, lane)| !lane.bidirectional) {
+        let (v1, v2) = match (level.vertices.get(lane.start), level.vertices.get(lane.end)) {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => continue,
+        };
+        let arrows = match arrow_mesh(xf.to_world(v1.x, v1.y), xf.to_world(v2.x, v2.y)) {
+            Some(arrows) => arrows,
+            None => continue,
+        };
+        // just above the lane itself
+        let z = elevation + 0.015 + 0.001 * i as f32;
+        commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(arrows),
+            material: handles.lane_arrow_material.clone(),
+            transform: Transform::from_xyz(0., 0., z),
+            ..Default::default()
+        })
+        .insert(LaneArrow)
+        .insert(SiteMapEntity);
+    }
 
+    for (i, door) in level.doors.iter().enumerate() {
+        let (v1, v2) = match (level.vertices.get(door.start), level.vertices.get(door.end)) {
+            (Some(v1), Some(v2)) => (v1, v2),
+            _ => continue,
+        };
+        let p1 = xf.to_world(v1.x, v1.y);
+        let p2 = xf.to_world(v2.x, v2.y);
+        let as_vec2 = |p: [f64; 2]| Vec2::new(p[0] as f32, p[1] as f32);
+        let (thickness, height) = (DOOR_THICKNESS as f32, DOOR_HEIGHT as f32);
+
+        for panel in door_panels(door, [p1.x as f64, p1.y as f64], [p2.x as f64, p2.y as f64]) {
+            let (pivot, dir) = (as_vec2(panel.pivot), as_vec2(panel.dir));
+            let (length, swing) = (panel.length as f32, panel.swing as f32);
+            let yaw = dir.y.atan2(dir.x);
+            let center = pivot + dir * (length / 2.);
             commands.spawn_bundle(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::from([length, width])))),
-                material: lane_material_handle.clone(),
+                mesh: meshes.add(Mesh::from(shape::Box::new(length, thickness, height))),
+                material: handles.door_material.clone(),
                 transform: Transform {
-                    translation: Vec3::new(cx, cy, z_ofs),
+                    translation: Vec3::new(center.x, center.y, elevation + height / 2.),
                     rotation: Quat::from_rotation_z(yaw),
                     ..Default::default()
                 },
                 ..Default::default()
-            });
-            z_ofs += 0.001;  // avoid flicker
+            })
+            .insert(ElementId::new(&level.name, ElementKind::Door, i))
+            .insert(SiteMapEntity);
+
+            let indicator = if swing != 0. {
+                PbrBundle {
+                    mesh: meshes.add(sector_mesh(length, yaw, swing)),
+                    material: handles.door_swing_material.clone(),
+                    transform: Transform::from_xyz(pivot.x, pivot.y, elevation + 0.005),
+                    ..Default::default()
+                }
+            } else {
+                // sliding panels: show the track the panel slides into
+                let track = pivot - dir * (length / 2.);
+                PbrBundle {
+                    mesh: meshes.add(Mesh::from(shape::Quad::new(Vec2::new(length, 0.15)))),
+                    material: handles.door_swing_material.clone(),
+                    transform: Transform {
+                        translation: Vec3::new(track.x, track.y, elevation + 0.005),
+                        rotation: Quat::from_rotation_z(yaw),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+            };
+            commands.spawn_bundle(indicator).insert(DoorSwing).insert(SiteMapEntity);
         }
+    }
+}
 
-        let wall_material_handle = materials.add(Color::rgb(0.5, 0.5, 1.0).into());
+/// The floor-plan underlay of a level.
+pub struct DrawingUnderlay {
+    image: Handle<Image>,
+    transform: LevelTransform,
+    /// Width and height of the image in pixels, once it has loaded.
+    size: Option<(f64, f64)>,
+}
 
-        for wall in &self.walls {
-            let v1 = &self.vertices[wall.start];
-            let v2 = &self.vertices[wall.end];
-            let v1x = ((v1.x - ofs_x) * scale) as f32;
-            let v1y = ((v1.y - ofs_y) * scale) as f32;
-            let v2x = ((v2.x - ofs_x) * scale) as f32;
-            let v2y = ((v2.y - ofs_y) * scale) as f32;
+impl DrawingUnderlay {
+    /// Stretches the drawing over its level, if its size is known yet.
+    fn fit(&self, transform: &mut Transform) {
+        let (width, height) = match self.size {
+            Some(size) => size,
+            None => return,
+        };
+        let xf = &self.transform;
+        let center = xf.to_world(width / 2., height / 2.);
+        transform.translation.x = center.x;
+        transform.translation.y = center.y;
+        transform.scale = Vec3::new((width * xf.scale) as f32, (height * xf.scale) as f32, 1.);
+    }
+}
 
-            let dx = v2x - v1x;
-            let dy = v2y - v1y;
-            let length = Vec2::from([dx, dy]).length();
-            let width = 0.1 as f32;
-            let height = 1.0 as f32;
-            let yaw = dy.atan2(dx);
-            let cx = (v1x + v2x) / 2.;
-            let cy = (v1y + v2y) / 2.;
+fn pixel_size(image: &Image) -> (f64, f64) {
+    let size = image.texture_descriptor.size;
+    (size.width as f64, size.height as f64)
+}
 
-            commands.spawn_bundle(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::Box::new(length, width, height))),
-                material: wall_material_handle.clone(),
-                transform: Transform {
-                    translation: Vec3::new(cx, cy, height / 2.),
-                    rotation: Quat::from_rotation_z(yaw),
+/// Fits each drawing over its level once its image has loaded, and again
+/// whenever the image changes.
+fn fit_drawings(
+    mut events: EventReader<AssetEvent<Image>>,
+    images: Res<Assets<Image>>,
+    mut drawings: Query<(&mut DrawingUnderlay, &mut Transform)>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Modified { handle } = event {
+            for (mut drawing, _) in drawings.iter_mut() {
+                if drawing.image == *handle {
+                    drawing.size = None;
+                }
+            }
+        }
+    }
+    for (mut drawing, mut transform) in drawings.iter_mut() {
+        if drawing.size.is_some() {
+            continue;
+        }
+        if let Some(image) = images.get(&drawing.image) {
+            drawing.size = Some(pixel_size(image));
+            drawing.fit(&mut transform);
+        }
+    }
+}
+
+/// Marks floors whose texture has to tile rather than stretch.
+pub struct FloorTexture(Handle<Image>);
+
+fn spawn_floors(
+    level: &Level,
+    xf: &LevelTransform,
+    commands: &mut Commands,
+    meshes: &mut ResMut<Assets<Mesh>>,
+    materials: &mut ResMut<Assets<StandardMaterial>>,
+    asset_server: &AssetServer,
+    texture_settings: &TextureSettings,
+) {
+    let polygon = |indices: &[usize]| -> Vec<[f64; 2]> {
+        indices.iter()
+            .filter_map(|&i| level.vertices.get(i))
+            .map(|v| {
+                let p = xf.to_world(v.x, v.y);
+                [p.x as f64, p.y as f64]
+            })
+            .collect()
+    };
+    let holes: Vec<Vec<[f64; 2]>> = level.holes.iter().map(|h| polygon(&h.vertices)).collect();
+
+    for floor in &level.floors {
+        let outline = polygon(&floor.vertices);
+        let floor_holes: Vec<Vec<[f64; 2]>> = holes.iter()
+            .filter(|h| !h.is_empty() && triangulate::contains(&outline, h[0]))
+            .cloned()
+            .collect();
+        let triangles = triangulate::triangulate(&outline, &floor_holes);
+        if triangles.is_empty() {
+            continue;
+        }
+
+        let rotation = floor.texture_rotation().to_radians();
+        let (sin, cos) = (rotation.sin(), rotation.cos());
+        let tile = floor.texture_scale().max(1e-3);
+        let points: Vec<[f64; 2]> = outline.iter().chain(floor_holes.iter().flatten()).cloned().collect();
+        let positions: Vec<[f32; 3]> = points.iter().map(|p| [p[0] as f32, p[1] as f32, 0.]).collect();
+        let uvs: Vec<[f32; 2]> = points.iter().map(|p| {
+            [
+                ((p[0] * cos + p[1] * sin) / tile) as f32,
+                ((-p[0] * sin + p[1] * cos) / tile) as f32,
+            ]
+        }).collect();
+        let normals = vec![[0., 0., 1.]; positions.len()];
+        let indices = triangles.iter().flat_map(|t| t.iter().map(|&i| i as u32)).collect();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+        mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+        mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+        mesh.set_indices(Some(Indices::U32(indices)));
+
+        // floors whose texture is missing are drawn plain grey
+        let texture: Option<Handle<Image>> = texture_settings
+            .find(floor.texture_name())
+            .map(|path| asset_server.load(path.as_path()));
+        let material = materials.add(StandardMaterial {
+            base_color: if texture.is_some() { Color::WHITE } else { Color::rgb(0.3, 0.3, 0.3) },
+            base_color_texture: texture.clone(),
+            perceptual_roughness: 0.9,
+            ..Default::default()
+        });
+
+        let mut entity = commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(mesh),
+            material,
+            transform: Transform::from_xyz(0., 0., xf.elevation),
+            ..Default::default()
+        });
+        entity.insert(SiteMapEntity);
+        if let Some(texture) = texture {
+            entity.insert(FloorTexture(texture));
+        }
+    }
+}
+
+/// Where to look for the glTF files of the models placed on each level.
+pub struct ModelSettings {
+    /// `OpenRobotics/OfficeChairBlack` is loaded from
+    /// `<directory>/OpenRobotics/OfficeChairBlack.glb` (or `.gltf`).
+    pub directory: PathBuf,
+}
+
+impl Default for ModelSettings {
+    fn default() -> Self {
+        ModelSettings {
+            directory: env::var_os("TRAFFIC_EDITOR_MODEL_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("models")),
+        }
+    }
+}
+
+impl ModelSettings {
+    /// The model file for `model_name`, if there is one on disk. The path
+    /// is absolute so that the asset server does not look for it under its
+    /// own asset directory.
+    pub fn find(&self, model_name: &str) -> Option<PathBuf> {
+        if cfg!(target_arch = "wasm32") {
+            return None;
+        }
+        ["glb", "gltf"].iter()
+            .find_map(|ext| self.directory.join(format!("{}.{}", model_name, ext)).canonicalize().ok())
+    }
+}
+
+/// Where to look for the images named by each floor's `texture_name`.
+pub struct TextureSettings {
+    /// `blue_linoleum` is loaded from `<directory>/blue_linoleum.png`.
+    pub directory: PathBuf,
+}
+
+impl Default for TextureSettings {
+    fn default() -> Self {
+        TextureSettings {
+            directory: env::var_os("TRAFFIC_EDITOR_TEXTURE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("textures")),
+        }
+    }
+}
+
+impl TextureSettings {
+    /// The image file for `texture_name`, if there is one on disk. The path
+    /// is absolute so that the asset server does not look for it under its
+    /// own asset directory.
+    pub fn find(&self, texture_name: &str) -> Option<PathBuf> {
+        if cfg!(target_arch = "wasm32") || texture_name.is_empty() || texture_name == "default" {
+            return None;
+        }
+        self.directory.join(format!("{}.png", texture_name)).canonicalize().ok()
+    }
+}
+
+/// Stands in for a model whose file could not be found.
+pub struct ModelPlaceholder {
+    pub label: String,
+}
+
+fn spawn_models(
+    level: &Level,
+    xf: &LevelTransform,
+    handles: &SiteHandles,
+    commands: &mut Commands,
+    asset_server: &AssetServer,
+    model_settings: &ModelSettings,
+) {
+    for model in &level.models {
+        let p = xf.to_world(model.x, model.y);
+        let transform = Transform {
+            translation: Vec3::new(p.x, p.y, xf.elevation + model.z as f32),
+            rotation: Quat::from_rotation_z(model.yaw as f32),
+            ..Default::default()
+        };
+
+        match model_settings.find(&model.model_name) {
+            Some(path) => {
+                let scene: Handle<Scene> =
+                    asset_server.load(format!("{}#Scene0", path.display()).as_str());
+                commands
+                    .spawn_bundle((transform, GlobalTransform::identity(), SiteMapEntity))
+                    .with_children(|parent| {
+                        parent.spawn_scene(scene);
+                    });
+            }
+            None => {
+                let mut transform = transform;
+                transform.translation.z += 0.25;
+                commands.spawn_bundle(PbrBundle {
+                    mesh: handles.placeholder_mesh.clone(),
+                    material: handles.placeholder_material.clone(),
+                    transform,
                     ..Default::default()
-                },
-                ..Default::default()
+                })
+                .insert(ModelPlaceholder { label: model.model_name.clone() })
+                .insert(SiteMapEntity);
+            }
+        }
+    }
+}
+
+/// Draws the model name next to each placeholder box.
+fn label_model_placeholders(
+    egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    cameras: Query<(&GlobalTransform, &FlexibleProjection)>,
+    placeholders: Query<(&GlobalTransform, &ModelPlaceholder)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let (camera_transform, projection) = cameras.single();
+    for (i, (transform, placeholder)) in placeholders.iter().enumerate() {
+        let screen = match projection.world_to_screen(
+            camera_transform, window_size, transform.translation) {
+            Some(screen) => screen,
+            None => continue,
+        };
+        egui::Area::new(format!("model_placeholder_{}", i))
+            .fixed_pos(egui::pos2(screen.x, window_size.y - screen.y))
+            .interactable(false)
+            .show(egui_context.ctx(), |ui| {
+                ui.small(&placeholder.label);
+            });
+    }
+}
+
+/// The waypoint roles of a vertex, for its label in the 2D view.
+fn waypoint_label(v: &Vertex) -> String {
+    let mut roles = Vec::new();
+    if v.is_charger() {
+        roles.push(String::from("charger"));
+    }
+    if v.is_parking_spot() {
+        roles.push(String::from("parking"));
+    }
+    if v.is_holding_point() {
+        roles.push(String::from("holding"));
+    }
+    if !v.spawn_robot_name().is_empty() {
+        roles.push(format!("spawns {} ({})", v.spawn_robot_name(), v.spawn_robot_type()));
+    }
+    if !v.dropoff_ingestor().is_empty() {
+        roles.push(format!("dropoff: {}", v.dropoff_ingestor()));
+    }
+    if !v.pickup_dispenser().is_empty() {
+        roles.push(format!("pickup: {}", v.pickup_dispenser()));
+    }
+    roles.join(" · ")
+}
+
+/// Labels the vertices that have waypoint roles, in the 2D view.
+fn label_waypoints(
+    sm: Res<SiteMap>,
+    egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    cameras: Query<(&GlobalTransform, &FlexibleProjection)>,
+    vertices: Query<(&GlobalTransform, &ElementId)>,
+) {
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let (camera_transform, projection) = cameras.single();
+    if projection.mode != ProjectionMode::Orthographic {
+        return;
+    }
+    for (transform, id) in vertices.iter().filter(|(_, id)| id.kind == ElementKind::Vertex) {
+        let v = match sm.levels.get(&id.level).and_then(|l| l.vertices.get(id.index)) {
+            Some(v) => v,
+            None => continue,
+        };
+        let label = waypoint_label(v);
+        if label.is_empty() {
+            continue;
+        }
+        let screen = match projection.world_to_screen(
+            camera_transform, window_size, transform.translation) {
+            Some(screen) => screen,
+            None => continue,
+        };
+        egui::Area::new(format!("waypoint_{}_{}", id.level, id.index))
+            .fixed_pos(egui::pos2(screen.x + 8., window_size.y - screen.y))
+            .interactable(false)
+            .show(egui_context.ctx(), |ui| {
+                ui.small(label);
             });
+    }
+}
+
+/// Images load with clamped sampling; switch floor textures to repeat once
+/// they arrive so that `texture_scale` tiles them across the floor.
+fn repeat_floor_textures(
+    mut events: EventReader<AssetEvent<Image>>,
+    mut images: ResMut<Assets<Image>>,
+    floors: Query<&FloorTexture>,
+) {
+    for event in events.iter() {
+        if let AssetEvent::Created { handle } = event {
+            if floors.iter().any(|f| f.0 == *handle) {
+                if let Some(image) = images.get_mut(handle) {
+                    image.sampler_descriptor.address_mode_u = AddressMode::Repeat;
+                    image.sampler_descriptor.address_mode_v = AddressMode::Repeat;
+                }
+            }
+        }
+    }
+}
+
+/// Door swings and lane arrows are markings for the 2D view only.
+fn update_door_swing_visibility(
+    projection: Query<&FlexibleProjection>,
+    mut swings: Query<&mut Visibility, Or<(With<DoorSwing>, With<LaneArrow>)>>,
+) {
+    let show = projection.single().mode == ProjectionMode::Orthographic;
+    for mut visibility in swings.iter_mut() {
+        if visibility.is_visible != show {
+            visibility.is_visible = show;
         }
     }
 }
 
 pub fn initialize_site_map(
     mut sm: ResMut<SiteMap>,
-    commands: Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    materials: ResMut<Assets<StandardMaterial>>,
+    mut status: ResMut<SiteMapStatus>,
+    command: Res<Command>,
+) {
+    let result = match &*command {
+        Command::Edit { filename: Some(filename) } => {
+            println!("parsing...");
+            sm.load(filename.clone())
+        }
+        _ => sm.load_demo(),
+    };
+    match result {
+        Ok(()) => println!("parsing complete"),
+        Err(e) => {
+            println!("could not load site map: {}", e);
+            status.error = Some(e.to_string());
+        }
+    }
+}
+
+/// Replaces all the map's entities when the map asks for it, which it does
+/// after every load.
+fn respawn_site_map(
+    mut sm: ResMut<SiteMap>,
+    mut commands: Commands,
+    entities: Query<Entity, With<SiteMapEntity>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
+    handles: Res<SiteHandles>,
+    model_settings: Res<ModelSettings>,
+    texture_settings: Res<TextureSettings>,
 ) {
-    let args: Vec<String> = env::args().collect();
-    if args.len() >= 2 {
-        println!("parsing...");
-        sm.load(args[1].clone());
-        sm.spawn(commands, meshes, materials, asset_server);
-        println!("parsing complete");
-    } else {
-        sm.load_demo();
-        sm.spawn(commands, meshes, materials, asset_server);
+    if !sm.respawn {
+        return;
     }
+    sm.respawn = false;
+    for entity in entities.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    sm.spawn(&mut commands, &mut meshes, &mut materials, &asset_server, &images, &handles, &model_settings, &texture_settings);
 }
 
 #[derive(Default)]
@@ -276,6 +1311,69 @@ pub struct SiteMapPlugin;
 impl Plugin for SiteMapPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<SiteMap>()
-           .add_startup_system(initialize_site_map);
+           .init_resource::<SiteMapStatus>()
+           .init_resource::<SiteHandles>()
+           .add_startup_system(initialize_site_map)
+           .add_system(respawn_site_map)
+           .add_system(update_door_swing_visibility)
+           .init_resource::<ModelSettings>()
+           .init_resource::<TextureSettings>()
+           .add_system(repeat_floor_textures)
+           .add_system(fit_drawings)
+           .add_system(label_model_placeholders)
+           .add_system(label_waypoints);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::building_map::Param;
+    use crate::edit::Element;
+
+    /// A map respawned after an edit finds its drawings already loaded, so
+    /// they have to be sized without waiting for a load event.
+    #[test]
+    fn respawned_drawing_is_visible() {
+        let xf = LevelTransform { ofs_x: 1., ofs_y: -2., scale: 0.05, elevation: 0. };
+        let underlay = DrawingUnderlay { image: Handle::default(), transform: xf, size: Some((400., 200.)) };
+        let mut transform = Transform { scale: Vec3::ZERO, ..Default::default() };
+        underlay.fit(&mut transform);
+        assert_eq!(transform.scale, Vec3::new(20., 10., 1.));
+        assert_eq!(transform.translation.truncate(), xf.to_world(200., 100.));
+
+        // one still loading stays hidden until fit_drawings() sizes it
+        let loading = DrawingUnderlay { size: None, ..underlay };
+        let mut transform = Transform { scale: Vec3::ZERO, ..Default::default() };
+        loading.fit(&mut transform);
+        assert_eq!(transform.scale, Vec3::ZERO);
+    }
+
+    #[test]
+    fn editing_a_measurement_rescales_its_level() {
+        let mut sm = SiteMap::default();
+        sm.load_yaml("\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices: [[0, 0, 0, ''], [100, 0, 0, '']]
+    measurements: [[0, 1, {distance: [3, 2]}]]
+").unwrap();
+        let loaded = *sm.level_transform("L1").unwrap();
+        assert_eq!(loaded.scale, 0.02);
+
+        let before = Element::get(&sm.levels()["L1"], ElementKind::Measurement, 0).unwrap();
+        let mut after = before.clone();
+        if let Element::Measurement(edge) = &mut after {
+            edge.params.insert("distance".into(), Param::Double(5.));
+        }
+        sm.apply(Edit::Replace { level: "L1".into(), index: 0, before, after });
+        let edited = *sm.level_transform("L1").unwrap();
+        assert_eq!(edited.scale, 0.05);
+        assert_eq!((edited.ofs_x, edited.ofs_y), (loaded.ofs_x, loaded.ofs_y));
+
+        sm.undo();
+        assert_eq!(sm.level_transform("L1").unwrap().scale, 0.02);
     }
 }
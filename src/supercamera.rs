@@ -1,4 +1,5 @@
 use bevy::{
+    core::Time,
     core_pipeline::ClearColor,
     ecs::bundle::Bundle,
     ecs::prelude::*,
@@ -6,7 +7,7 @@ use bevy::{
         Input,
         mouse::{MouseButton, MouseWheel},
     },
-    math::{Vec2, Vec3, Mat3, Mat4, Quat},
+    math::{Vec2, Vec3, Vec4, Mat3, Mat4, Quat},
     prelude::{App, Assets, CoreStage, GlobalTransform, Plugin, Transform},
     pbr2::{StandardMaterial},
     reflect::Reflect,
@@ -31,29 +32,79 @@ use bevy::{
 };
 use bevy::ecs as bevy_ecs;
 use bevy::reflect as bevy_reflect;
+use serde::{Deserialize, Serialize};
 
 extern crate web_sys;
 
-#[derive(PartialEq, Debug, Clone, Reflect)]
-pub enum ProjectionMode { Perspective, Orthographic }
+#[derive(PartialEq, Debug, Clone, Reflect, Serialize, Deserialize)]
+pub enum ProjectionMode {
+    Perspective,
+    Orthographic,
+    /// Perspective from a robot's point of view, moved by the walkthrough
+    /// module rather than by this one.
+    Walkthrough,
+}
 
 #[derive(Debug, Clone, Reflect)]
 #[reflect(Component)]
 pub struct FlexibleProjection {
     pub mode: ProjectionMode,
-    mode_switched: bool,
+    /// The mode being left, until the camera has been moved for the new one.
+    #[reflect(ignore)]
+    switched_from: Option<ProjectionMode>,
+    #[reflect(ignore)]
+    transition: Option<Transition>,
     persp: PerspectiveProjection,
     ortho: OrthographicProjection,
     orbit_center: Vec3,
     orbit_radius: f32,
+    /// What the camera turns around while orbiting: the point that was under
+    /// the cursor when the orbit started.
+    orbit_pivot: Vec3,
     orbit_upside_down: bool,
+    /// The 3D viewing angle to come back to after visiting the other views.
+    orbit_rotation: Quat,
+}
+
+/// Everything needed to bring the camera back to where it was.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraView {
+    pub mode: ProjectionMode,
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub ortho_scale: f32,
+    pub orbit_center: [f32; 3],
+    pub orbit_radius: f32,
+}
+
+/// How long a switch between projections takes, in seconds.
+const TRANSITION_TIME: f32 = 0.4;
+
+/// An animated switch between projections: both the projection matrix and
+/// the camera's pose are blended from where they were to where they go.
+#[derive(Debug, Clone)]
+struct Transition {
+    from_projection: Mat4,
+    from: Transform,
+    to: Transform,
+    /// From 0 to 1.
+    progress: f32,
+}
+
+impl Transition {
+    /// The progress eased in and out.
+    fn blend(&self) -> f32 {
+        let t = self.progress.clamp(0., 1.);
+        t * t * (3. - 2. * t)
+    }
 }
 
 impl Default for FlexibleProjection {
     fn default() -> Self {
         FlexibleProjection {
             mode: ProjectionMode::Orthographic,
-            mode_switched: true,
+            switched_from: None,
+            transition: None,
             persp: Default::default(),
             ortho: OrthographicProjection {
                 window_origin: WindowOrigin::Center,
@@ -63,17 +114,22 @@ impl Default for FlexibleProjection {
             },
             orbit_center: Vec3::ZERO,
             orbit_radius: 100.0,
+            orbit_pivot: Vec3::ZERO,
             orbit_upside_down: false,
+            orbit_rotation: Quat::from_rotation_x(std::f32::consts::FRAC_PI_4),
         }
     }
 }
 
 impl CameraProjection for FlexibleProjection {
     fn get_projection_matrix(&self) -> Mat4 {
-        if self.mode == ProjectionMode::Perspective {
-            return self.persp.get_projection_matrix();
-        } else {
-            return self.ortho.get_projection_matrix();
+        let matrix = self.mode_projection_matrix(&self.mode);
+        match &self.transition {
+            Some(transition) => {
+                let t = transition.blend();
+                transition.from_projection * (1. - t) + matrix * t
+            }
+            None => matrix,
         }
     }
 
@@ -83,29 +139,246 @@ impl CameraProjection for FlexibleProjection {
     }
 
     fn depth_calculation(&self) -> DepthCalculation {
-        if self.mode == ProjectionMode::Perspective {
-            return self.persp.depth_calculation();
-        } else {
+        if self.mode == ProjectionMode::Orthographic {
             return self.ortho.depth_calculation();
+        } else {
+            return self.persp.depth_calculation();
         }
     }
 
     fn far(&self) -> f32 {
-        if self.mode == ProjectionMode::Perspective {
-            return self.persp.far;
-        } else {
+        if self.mode == ProjectionMode::Orthographic {
             return self.ortho.far;
+        } else {
+            return self.persp.far;
         }
     }
 }
 
 impl FlexibleProjection {
     pub fn set_mode(&mut self, mode: ProjectionMode) {
+        if mode == self.mode {
+            return;
+        }
+        // several switches before the camera catches up count from the first
+        if self.switched_from.is_none() {
+            self.switched_from = Some(self.mode.clone());
+        }
         self.mode = mode;
-        self.mode_switched = true;
+    }
+
+    pub fn view(&self, transform: &Transform) -> CameraView {
+        CameraView {
+            mode: self.mode.clone(),
+            translation: transform.translation.into(),
+            rotation: Vec4::from(transform.rotation).into(),
+            ortho_scale: self.ortho.scale,
+            orbit_center: self.orbit_center.into(),
+            orbit_radius: self.orbit_radius,
+        }
+    }
+
+    /// Goes back to a view saved by `view()`, animating the way there
+    /// unless it is a walkthrough view.
+    pub fn restore(&mut self, transform: &mut Transform, view: &CameraView) {
+        let from_projection = self.get_projection_matrix();
+        let to = Transform {
+            translation: view.translation.into(),
+            rotation: Quat::from_vec4(Vec4::from(view.rotation)),
+            ..Default::default()
+        };
+        self.switched_from = None;
+        self.mode = view.mode.clone();
+        self.ortho.scale = view.ortho_scale;
+        self.orbit_center = view.orbit_center.into();
+        self.orbit_radius = view.orbit_radius;
+        match self.mode {
+            ProjectionMode::Walkthrough => {
+                // the walkthrough starts out from the orbit center
+                self.orbit_center = to.translation;
+                self.transition = None;
+                *transform = to;
+            }
+            _ => {
+                if self.mode == ProjectionMode::Perspective {
+                    self.orbit_rotation = to.rotation;
+                }
+                self.transition = Some(Transition { from_projection, from: *transform, to, progress: 0. });
+            }
+        }
+    }
+
+    fn mode_projection_matrix(&self, mode: &ProjectionMode) -> Mat4 {
+        if *mode == ProjectionMode::Orthographic {
+            self.ortho.get_projection_matrix()
+        } else {
+            self.persp.get_projection_matrix()
+        }
+    }
+
+    /// Fits the new mode's zoom to the old one's, so that the camera keeps
+    /// looking at the same area, and starts the transition to it. Switches
+    /// to the walkthrough are not animated: it places the camera itself.
+    fn switch_mode(&mut self, from_mode: ProjectionMode, transform: &mut Transform, ortho_height: f32) {
+        let from_projection = match &self.transition {
+            Some(_) => self.get_projection_matrix(),
+            None => self.mode_projection_matrix(&from_mode),
+        };
+        let center = match from_mode {
+            ProjectionMode::Perspective => self.orbit_center,
+            ProjectionMode::Orthographic => transform.translation.truncate().extend(self.orbit_center.z),
+            ProjectionMode::Walkthrough => transform.translation,
+        };
+        if from_mode == ProjectionMode::Perspective {
+            self.orbit_rotation = transform.rotation;
+        }
+        // the height seen by the orthographic view is 2 * scale
+        let tan_half_fov = (self.persp.fov / 2.).tan();
+        match (&from_mode, &self.mode) {
+            (ProjectionMode::Perspective, ProjectionMode::Orthographic) => {
+                self.ortho.scale = self.orbit_radius * tan_half_fov;
+            }
+            (ProjectionMode::Orthographic, ProjectionMode::Perspective) => {
+                self.orbit_radius = self.ortho.scale / tan_half_fov;
+            }
+            _ => (),
+        }
+        self.orbit_center = center;
+
+        let to = match self.mode {
+            ProjectionMode::Orthographic => Transform::from_translation(
+                Vec3::new(center.x, center.y, center.z + ortho_height)),
+            ProjectionMode::Perspective => Transform {
+                translation: center + self.orbit_rotation * Vec3::new(0., 0., self.orbit_radius),
+                rotation: self.orbit_rotation,
+                ..Default::default()
+            },
+            ProjectionMode::Walkthrough => {
+                self.transition = None;
+                return;
+            }
+        };
+        self.transition = Some(Transition {
+            from_projection,
+            from: *transform,
+            to,
+            progress: 0.,
+        });
+    }
+
+    pub fn orbit_center(&self) -> Vec3 {
+        self.orbit_center
+    }
+
+    /// Moves the camera so that it looks at `point`, keeping its zoom and,
+    /// in 3D, its viewing angle. The walkthrough camera stays where it is.
+    pub fn focus(&mut self, transform: &mut Transform, point: Vec3) {
+        if self.mode == ProjectionMode::Orthographic {
+            transform.translation.x = point.x;
+            transform.translation.y = point.y;
+        } else if self.mode == ProjectionMode::Perspective {
+            self.orbit_center = point;
+            transform.translation = point + transform.rotation * Vec3::new(0.0, 0.0, self.orbit_radius);
+        }
+    }
+
+    /// Zooms and moves the camera so that the box from `min` to `max` fills
+    /// the view, in both modes, keeping the 3D viewing angle. Tiny boxes,
+    /// like a single vertex, are shown with a few metres around them.
+    pub fn frame(&mut self, transform: &mut Transform, min: Vec3, max: Vec3, aspect_ratio: f32) {
+        let margin = 1.1;
+        let center = (min + max) / 2.;
+        let half = ((max - min) / 2.).max(Vec3::splat(2.));
+
+        self.ortho.scale = f32::max(half.y, half.x / aspect_ratio) * margin;
+        // fit the bounding sphere inside the narrower of the two fields of view
+        let fov = f32::min(self.persp.fov, 2. * ((self.persp.fov / 2.).tan() * aspect_ratio).atan());
+        self.orbit_radius = half.length() / (fov / 2.).sin() * margin;
+
+        if self.mode == ProjectionMode::Orthographic {
+            transform.translation.x = center.x;
+            transform.translation.y = center.y;
+            self.orbit_center = center;
+        } else if self.mode == ProjectionMode::Perspective {
+            self.focus(transform, center);
+        }
+    }
+
+    /// Projects a world point into window coordinates (pixels, origin at the
+    /// bottom left, like cursor positions). None if it is behind the camera.
+    pub fn world_to_screen(
+        &self,
+        camera_transform: &GlobalTransform,
+        window_size: Vec2,
+        point: Vec3,
+    ) -> Option<Vec2> {
+        let view_projection =
+            self.get_projection_matrix() * camera_transform.compute_matrix().inverse();
+        let clip = view_projection * point.extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let ndc = clip.truncate() / clip.w;
+        Some((Vec2::new(ndc.x, ndc.y) + Vec2::ONE) / 2.0 * window_size)
+    }
+
+    /// The ray through a window position (pixels, origin at the bottom
+    /// left), as a point on the near plane and a unit direction.
+    pub fn screen_to_ray(
+        &self,
+        camera_transform: &GlobalTransform,
+        window_size: Vec2,
+        screen: Vec2,
+    ) -> (Vec3, Vec3) {
+        let ndc = screen / window_size * 2.0 - Vec2::ONE;
+        let ndc_to_world =
+            camera_transform.compute_matrix() * self.get_projection_matrix().inverse();
+        // depth is reversed: 1 is the near plane, 0 is infinitely far away
+        let near = ndc_to_world.project_point3(ndc.extend(1.0));
+        let further = ndc_to_world.project_point3(ndc.extend(0.5));
+        (near, (further - near).normalize())
+    }
+}
+
+/// The point of the site under the cursor, found by whichever system knows
+/// the site's geometry. The camera zooms toward it and orbits around it,
+/// falling back to the ground plane where there is nothing under the cursor.
+#[derive(Default)]
+pub struct CursorPoint(pub Option<Vec3>);
+
+/// How far to move the orthographic camera when its scale changes, so that
+/// the point under the cursor (in pixels, origin at the bottom left) stays
+/// under it.
+fn ortho_zoom_shift(cursor: Vec2, window_size: Vec2, old_scale: f32, new_scale: f32) -> Vec2 {
+    let aspect_ratio = window_size.x / window_size.y;
+    let ndc = cursor / window_size * 2. - Vec2::ONE;
+    ndc * Vec2::new(aspect_ratio, 1.) * (old_scale - new_scale)
+}
+
+/// The orbit center once the orbit radius is scaled by `ratio`: the whole
+/// view is scaled about `target`, so that it stays under the cursor.
+fn zoom_about(orbit_center: Vec3, target: Vec3, ratio: f32) -> Vec3 {
+    target + (orbit_center - target) * ratio
+}
+
+/// Where a ray meets the ground plane z = 0, if it does.
+fn ground_point(origin: Vec3, dir: Vec3) -> Option<Vec3> {
+    if dir.z.abs() < 1e-6 {
+        return None;
+    }
+    let t = -origin.z / dir.z;
+    if t < 0. {
+        None
+    } else {
+        Some(origin + dir * t)
     }
 }
 
+/// Set while some other system owns the mouse (for example while dragging
+/// a vertex), so that mouse motion does not also pan or orbit the camera.
+#[derive(Default)]
+pub struct MouseCaptured(pub bool);
+
 #[derive(Bundle)]
 pub struct SuperCameraBundle {
     pub camera: Camera,
@@ -178,6 +451,9 @@ fn supercamera_motion(
     mut ev_scroll: EventReader<MouseWheel>,
     input_mouse: Res<Input<MouseButton>>,
     mut previous_mouse_location: ResMut<MouseLocation>,
+    mouse_captured: Res<MouseCaptured>,
+    cursor_point: Res<CursorPoint>,
+    time: Res<Time>,
     mut query: Query<(&mut Camera, &mut Transform, &mut FlexibleProjection, &Vec3)>,
 ) {
     let pan_button = MouseButton::Left;
@@ -191,7 +467,7 @@ fn supercamera_motion(
     }
 
     let mut cursor_motion = Vec2::ZERO;
-    if input_mouse.pressed(pan_button) || input_mouse.pressed(orbit_button) {
+    if !mouse_captured.0 && (input_mouse.pressed(pan_button) || input_mouse.pressed(orbit_button)) {
         cursor_motion.x = last_pos.x - previous_mouse_location.previous.x;
         cursor_motion.y = last_pos.y - previous_mouse_location.previous.y;
     }
@@ -231,20 +507,26 @@ fn supercamera_motion(
         initial_position
     ) = query.single_mut();
 
-    if proj.mode_switched {
-        proj.mode_switched = false;
-        transform.translation = initial_position.clone();
-        transform.rotation = Quat::default();
-        /*
-        if proj.mode == ProjectionMode::Perspective {
-            transform.translation.z = proj.orbit_radius;
-            println!("set transform translation to {}", transform.translation);
+    if let Some(from_mode) = proj.switched_from.take() {
+        proj.switch_mode(from_mode, &mut transform, initial_position.z);
+    }
+    if let Some(mut transition) = proj.transition.take() {
+        transition.progress += time.delta_seconds() / TRANSITION_TIME;
+        let t = transition.blend();
+        transform.translation = transition.from.translation.lerp(transition.to.translation, t);
+        transform.rotation = transition.from.rotation.slerp(transition.to.rotation, t);
+        if transition.progress < 1. {
+            proj.transition = Some(transition);
         }
-        */
+        // the user takes over once the camera has arrived
+        return;
     }
 
     if proj.mode == ProjectionMode::Orthographic {
-        let window = windows.get_primary().unwrap();
+        let window = match windows.get_primary() {
+            Some(window) => window,
+            None => return,
+        };
         let window_size = Vec2::new(
             window.width() as f32,
             window.height() as f32);
@@ -260,10 +542,19 @@ fn supercamera_motion(
             transform.translation += right + up;
         }
         if scroll.abs() > 0.0 {
+            let old_scale = proj.ortho.scale;
             proj.ortho.scale -= scroll * proj.ortho.scale * 0.1;
             proj.ortho.scale = f32::max(proj.ortho.scale, 0.02);
+            // keep the world point under the cursor where it is on screen
+            if let Some(cursor) = window.cursor_position() {
+                let shift = ortho_zoom_shift(cursor, window_size, old_scale, proj.ortho.scale);
+                transform.translation += shift.extend(0.);
+            }
         }
-    } else {
+        // so that the other views start out over the same spot
+        proj.orbit_center.x = transform.translation.x;
+        proj.orbit_center.y = transform.translation.y;
+    } else if proj.mode == ProjectionMode::Perspective {
         // perspective mode
         if input_mouse.just_released(orbit_button) || input_mouse.just_pressed(orbit_button) {
             // only check for upside down when orbiting started or ended this frame
@@ -271,12 +562,27 @@ fn supercamera_motion(
             let up = transform.rotation * Vec3::Z;
             proj.orbit_upside_down = up.z <= 0.0;
         }
+        let window = match windows.get_primary() {
+            Some(window) => window,
+            None => return,
+        };
+        let window_size = Vec2::new(window.width() as f32, window.height() as f32);
+        // what the cursor points at, for orbiting around and zooming toward
+        let picked_point = |proj: &FlexibleProjection, transform: &Transform| -> Option<Vec3> {
+            if cursor_point.0.is_some() {
+                return cursor_point.0;
+            }
+            let cursor = window.cursor_position()?;
+            let (origin, dir) = proj.screen_to_ray(&GlobalTransform::from(*transform), window_size, cursor);
+            ground_point(origin, dir)
+        };
+        if input_mouse.just_pressed(orbit_button) && !mouse_captured.0 {
+            proj.orbit_pivot = picked_point(&proj, &transform).unwrap_or(proj.orbit_center);
+        }
 
         let mut any = false;
         if input_mouse.pressed(orbit_button) && cursor_motion.length_squared() > 0. {
             any = true;
-            let window = windows.get_primary().unwrap();
-            let window_size = Vec2::new(window.width() as f32, window.height() as f32);
             let delta_x = {
                 let delta = cursor_motion.x / window_size.x * std::f32::consts::PI * 2.0;
                 if proj.orbit_upside_down { -delta } else { delta }
@@ -284,14 +590,15 @@ fn supercamera_motion(
             let delta_y = -cursor_motion.y / window_size.y * std::f32::consts::PI;
             let yaw = Quat::from_rotation_z(-delta_x);
             let pitch = Quat::from_rotation_x(-delta_y);
+            let old_rotation = transform.rotation;
             transform.rotation = yaw * transform.rotation; // global y
             transform.rotation = transform.rotation * pitch; // local x
+            // swing the orbit center around the pivot by the same turn
+            let turn = transform.rotation * old_rotation.inverse();
+            proj.orbit_center = proj.orbit_pivot + turn * (proj.orbit_center - proj.orbit_pivot);
         } else if input_mouse.pressed(MouseButton::Left) && cursor_motion.length_squared() > 0. {
             any = true;
             // make panning distance independent of resolution and FOV,
-            let window = windows.get_primary().unwrap();
-            let window_size = Vec2::new(window.width() as f32, window.height() as f32);
-
             cursor_motion *=
                 Vec2::new(
                     proj.persp.fov * proj.persp.aspect_ratio,
@@ -305,9 +612,13 @@ fn supercamera_motion(
             proj.orbit_center += translation;
         } else if scroll.abs() > 0.0 {
             any = true;
+            let target = picked_point(&proj, &transform).unwrap_or(proj.orbit_center);
+            let old_radius = proj.orbit_radius;
             proj.orbit_radius -= scroll * proj.orbit_radius * 0.2;
             // dont allow zoom to reach zero or you get stuck
             proj.orbit_radius = f32::max(proj.orbit_radius, 0.05);
+            let ratio = proj.orbit_radius / old_radius;
+            proj.orbit_center = zoom_about(proj.orbit_center, target, ratio);
         }
 
         if any {
@@ -329,7 +640,7 @@ fn supercamera_setup(
 ) {
     println!("supercamera_setup()");
     let mut cam = SuperCameraBundle::default();
-    // todo: calculate camera scale based on window size and map size, etc.
+    // a starting point only: the view is fitted to each map as it is loaded
     cam.flexible_projection.ortho.scale = 10.0;
     let start_z = 20.;
     cam.flexible_projection.orbit_radius = start_z;
@@ -338,15 +649,21 @@ fn supercamera_setup(
     commands.spawn_bundle(cam);
 }
 
+/// Label of the system that moves the camera from mouse input. Systems
+/// that capture the mouse should run before it.
+pub const SUPERCAMERA_MOTION: &str = "supercamera_motion";
+
 #[derive(Default)]
 pub struct SuperCameraPlugin;
 
 impl Plugin for SuperCameraPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<MouseLocation>()
+           .init_resource::<MouseCaptured>()
+           .init_resource::<CursorPoint>()
            .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
            .add_startup_system(supercamera_setup)
-           .add_system(supercamera_motion)
+           .add_system(supercamera_motion.label(SUPERCAMERA_MOTION))
            .add_system(update_frustum);
 
         app.register_type::<Camera>()
@@ -356,3 +673,113 @@ impl Plugin for SuperCameraPlugin {
             );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn frame_fits_the_box_in_both_views() {
+        let mut proj = FlexibleProjection::default();
+        let mut transform = Transform::from_xyz(0., 0., 20.);
+        proj.frame(&mut transform, Vec3::ZERO, Vec3::new(40., 10., 0.), 2.);
+        assert_eq!(transform.translation, Vec3::new(20., 5., 20.));
+        assert_eq!(proj.orbit_center, Vec3::new(20., 5., 0.));
+        // 40 m across a window twice as wide as it is high, with a margin
+        assert_close(proj.ortho.scale, 10. * 1.1);
+        // the box's bounding sphere, at least 2 m deep, in the vertical field of view
+        let radius = Vec3::new(20., 5., 2.).length();
+        assert_close(proj.orbit_radius, radius / (PI / 8.).sin() * 1.1);
+    }
+
+    #[test]
+    fn frame_fits_narrow_windows_by_their_width() {
+        let mut proj = FlexibleProjection::default();
+        let mut transform = Transform::default();
+        proj.frame(&mut transform, Vec3::ZERO, Vec3::new(10., 10., 0.), 0.5);
+        assert_close(proj.ortho.scale, 10. * 1.1);
+        let horizontal_fov = 2. * ((PI / 8.).tan() * 0.5).atan();
+        let radius = Vec3::new(5., 5., 2.).length();
+        assert_close(proj.orbit_radius, radius / (horizontal_fov / 2.).sin() * 1.1);
+    }
+
+    /// The world point under the cursor in the orthographic view.
+    fn ortho_point(camera: Vec2, scale: f32, cursor: Vec2, window_size: Vec2) -> Vec2 {
+        let ndc = cursor / window_size * 2. - Vec2::ONE;
+        camera + ndc * Vec2::new(window_size.x / window_size.y, 1.) * scale
+    }
+
+    #[test]
+    fn ortho_zoom_keeps_the_point_under_the_cursor() {
+        let window_size = Vec2::new(800., 600.);
+        let camera = Vec2::new(5., -3.);
+        for cursor in [Vec2::new(100., 500.), Vec2::new(400., 300.), Vec2::new(790., 10.)] {
+            let before = ortho_point(camera, 10., cursor, window_size);
+            let shifted = camera + ortho_zoom_shift(cursor, window_size, 10., 7.5);
+            let after = ortho_point(shifted, 7.5, cursor, window_size);
+            assert_close(before.distance(after), 0.);
+        }
+        assert_eq!(ortho_zoom_shift(Vec2::new(400., 300.), window_size, 10., 7.5), Vec2::ZERO);
+    }
+
+    #[test]
+    fn perspective_zoom_keeps_the_target_in_line() {
+        let rotation = Quat::from_rotation_x(PI / 4.);
+        let (center, radius) = (Vec3::new(1., 2., 0.), 10.);
+        let target = Vec3::new(4., 6., 0.);
+        let camera = center + rotation * Vec3::new(0., 0., radius);
+
+        let ratio = 0.8;
+        let zoomed_center = zoom_about(center, target, ratio);
+        let zoomed = zoomed_center + rotation * Vec3::new(0., 0., radius * ratio);
+        let (before, after) = ((target - camera).normalize(), (target - zoomed).normalize());
+        assert_close(before.distance(after), 0.);
+        assert_close((target - zoomed).length(), (target - camera).length() * ratio);
+    }
+
+    /// Switches modes the way the camera system does, and returns where the
+    /// camera is headed.
+    fn switch(proj: &mut FlexibleProjection, transform: &mut Transform, mode: ProjectionMode) -> Option<Transform> {
+        proj.set_mode(mode);
+        let from = proj.switched_from.take().unwrap();
+        proj.switch_mode(from, transform, 20.);
+        proj.transition.as_ref().map(|transition| transition.to)
+    }
+
+    #[test]
+    fn switching_views_keeps_the_same_area_in_view() {
+        let mut proj = FlexibleProjection::default();
+        proj.ortho.scale = 5.;
+        let mut transform = Transform::from_xyz(3., 4., 20.);
+
+        let to = switch(&mut proj, &mut transform, ProjectionMode::Perspective).unwrap();
+        let tan_half_fov = (PI / 8.).tan();
+        assert_close(proj.orbit_radius, 5. / tan_half_fov);
+        assert_eq!(proj.orbit_center, Vec3::new(3., 4., 0.));
+        let expected = proj.orbit_center + proj.orbit_rotation * Vec3::new(0., 0., proj.orbit_radius);
+        assert_close(to.translation.distance(expected), 0.);
+
+        transform = to;
+        proj.orbit_radius *= 2.;
+        let to = switch(&mut proj, &mut transform, ProjectionMode::Orthographic).unwrap();
+        assert_close(proj.ortho.scale, 10.);
+        assert_close(to.translation.distance(Vec3::new(3., 4., 20.)), 0.);
+
+        assert!(switch(&mut proj, &mut transform, ProjectionMode::Walkthrough).is_none());
+    }
+
+    #[test]
+    fn frame_shows_room_around_a_point() {
+        let mut proj = FlexibleProjection::default();
+        let mut transform = Transform::default();
+        let point = Vec3::new(3., 4., 0.);
+        proj.frame(&mut transform, point, point, 1.);
+        assert_close(proj.ortho.scale, 2. * 1.1);
+        assert_eq!(transform.translation, point);
+    }
+}
@@ -0,0 +1,276 @@
+use bevy::{
+    core::Time,
+    ecs::prelude::*,
+    input::{keyboard::KeyCode, mouse::MouseButton, Input},
+    math::Vec2,
+    prelude::{App, GlobalTransform, Plugin},
+    window::Windows,
+};
+use bevy_egui::{egui, EguiContext};
+
+use crate::building_map::{Edge, Lane, Level, Vertex};
+use crate::edit::{Edit, Element};
+use crate::site_map::SiteMap;
+use crate::supercamera::{FlexibleProjection, ProjectionMode};
+
+/// How close, in screen pixels, a click has to be to an existing vertex to
+/// reuse it instead of creating a new one.
+const SNAP_RADIUS: f32 = 10.0;
+
+/// The longest time, in seconds, between the two clicks of a double-click,
+/// which must also be within the snap radius of each other.
+const DOUBLE_CLICK_TIME: f64 = 0.4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tool {
+    Select,
+    AddLane,
+    AddWall,
+}
+
+/// One end of an edge being drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ToolPoint {
+    /// An existing vertex of the active level.
+    Vertex(usize),
+    /// A vertex to be created, in drawing coordinates.
+    New(f64, f64),
+}
+
+/// The tool picked in the toolbar, and the level it draws on.
+pub struct EditorTools {
+    pub tool: Tool,
+    pub level: String,
+    /// The first end of the edge being drawn; for walls, the end of the
+    /// chain drawn so far.
+    start: Option<ToolPoint>,
+    /// When and where on screen the last click was, to tell double-clicks.
+    last_click: Option<(f64, Vec2)>,
+}
+
+impl Default for EditorTools {
+    fn default() -> Self {
+        EditorTools {
+            tool: Tool::Select,
+            level: String::new(),
+            start: None,
+            last_click: None,
+        }
+    }
+}
+
+impl EditorTools {
+    pub fn set_tool(&mut self, tool: Tool) {
+        self.tool = tool;
+        self.start = None;
+        self.last_click = None;
+    }
+
+    /// The level to draw on: the one picked in the toolbar, or the first
+    /// level of the map if there is no valid pick.
+    pub fn active_level<'a>(&self, sm: &'a SiteMap) -> Option<&'a Level> {
+        sm.levels().get(&self.level).or_else(|| sm.levels().values().next())
+    }
+
+    pub fn toolbar(&mut self, ui: &mut egui::Ui, sm: &SiteMap) {
+        let tools = [(Tool::Select, "Select"), (Tool::AddLane, "Add Lane"), (Tool::AddWall, "Add Wall")];
+        for (tool, label) in tools {
+            if ui.add(egui::SelectableLabel::new(self.tool == tool, label)).clicked() {
+                self.set_tool(tool);
+            }
+        }
+        ui.separator();
+        let current = self.active_level(sm).map(|l| l.name.clone()).unwrap_or_default();
+        let mut picked = current.clone();
+        egui::ComboBox::from_label("Level")
+            .selected_text(&current)
+            .show_ui(ui, |ui| {
+                for name in sm.levels().keys() {
+                    ui.selectable_value(&mut picked, name.clone(), name);
+                }
+            });
+        if picked != current {
+            self.level = picked;
+            self.start = None;
+        }
+    }
+}
+
+/// The vertex of `level` nearest to the cursor within the snap radius, or
+/// else a new vertex under the cursor. Also returns where the point is on
+/// screen.
+fn point_under_cursor(
+    level: &Level,
+    sm: &SiteMap,
+    cursor: Vec2,
+    window_size: Vec2,
+    camera_transform: &GlobalTransform,
+    projection: &FlexibleProjection,
+) -> Option<(ToolPoint, Vec2)> {
+    let xf = sm.level_transform(&level.name)?;
+    let to_screen = |x: f64, y: f64| {
+        projection.world_to_screen(camera_transform, window_size, xf.to_world(x, y).extend(xf.elevation))
+    };
+    let snapped = level.vertices.iter().enumerate()
+        .filter_map(|(i, v)| to_screen(v.x, v.y).map(|s| (i, s, s.distance(cursor))))
+        .filter(|(_, _, distance)| *distance <= SNAP_RADIUS)
+        .min_by(|a, b| a.2.total_cmp(&b.2));
+    if let Some((i, screen, _)) = snapped {
+        return Some((ToolPoint::Vertex(i), screen));
+    }
+
+    let (origin, dir) = projection.screen_to_ray(camera_transform, window_size, cursor);
+    if dir.z.abs() < 1e-6 {
+        return None;
+    }
+    let p = origin + dir * ((xf.elevation - origin.z) / dir.z);
+    let (x, y) = xf.to_drawing(Vec2::new(p.x, p.y));
+    Some((ToolPoint::New(x, y), cursor))
+}
+
+/// The edit that adds an edge between two points as the `index`th element
+/// of its list, creating whichever of the points are new vertices first.
+/// Also returns the vertex index the end point will have.
+fn connect(
+    level: &Level,
+    start: ToolPoint,
+    end: ToolPoint,
+    wrap: fn(Edge) -> Element,
+    index: usize,
+) -> (Edit, usize) {
+    let mut edits = Vec::new();
+    let mut num_vertices = level.vertices.len();
+    let mut index_of = |p: ToolPoint| match p {
+        ToolPoint::Vertex(i) => i,
+        ToolPoint::New(x, y) => {
+            edits.push(Edit::Insert {
+                level: level.name.clone(),
+                index: num_vertices,
+                element: Element::Vertex(Vertex { x, y, ..Default::default() }),
+            });
+            num_vertices += 1;
+            num_vertices - 1
+        }
+    };
+    let edge = Edge {
+        start: index_of(start),
+        end: index_of(end),
+        params: Default::default(),
+    };
+    let end = edge.end;
+    edits.push(Edit::Insert { level: level.name.clone(), index, element: wrap(edge) });
+    (Edit::Batch(edits), end)
+}
+
+/// Add Lane: the first click in the 2D view picks the lane's start and the
+/// second its end. Escape abandons a lane that has only been started.
+///
+/// Add Wall: every click after the first adds a wall from the previous
+/// point, so that a room can be outlined in one go. Double-clicking the
+/// last point or pressing Escape ends the chain.
+fn draw_edges(
+    mut sm: ResMut<SiteMap>,
+    mut tools: ResMut<EditorTools>,
+    time: Res<Time>,
+    egui_context: Res<EguiContext>,
+    windows: Res<Windows>,
+    input_mouse: Res<Input<MouseButton>>,
+    keyboard_input: Res<Input<KeyCode>>,
+    cameras: Query<(&GlobalTransform, &FlexibleProjection)>,
+) {
+    if tools.tool != Tool::AddLane && tools.tool != Tool::AddWall {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        tools.start = None;
+    }
+    let (camera_transform, projection) = cameras.single();
+    if projection.mode != ProjectionMode::Orthographic {
+        return;
+    }
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+    let cursor = match window.cursor_position() {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let window_size = Vec2::new(window.width(), window.height());
+    let level = match tools.active_level(&sm) {
+        Some(level) => level,
+        None => return,
+    };
+    let (point, screen) = match point_under_cursor(
+        level, &sm, cursor, window_size, camera_transform, projection) {
+        Some(hit) => hit,
+        None => return,
+    };
+
+    // preview, in egui's coordinates (origin at the top left)
+    let to_egui = |p: Vec2| egui::pos2(p.x, window_size.y - p.y);
+    let painter = egui_context.ctx().layer_painter(
+        egui::LayerId::new(egui::Order::Foreground, egui::Id::new("tool_preview")));
+    let stroke = egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 160, 80));
+    if let ToolPoint::Vertex(_) = point {
+        painter.circle_stroke(to_egui(screen), SNAP_RADIUS, stroke);
+    }
+    if let Some(start) = tools.start {
+        let start_screen = match start {
+            ToolPoint::Vertex(i) => level.vertices.get(i).map(|v| (v.x, v.y)),
+            ToolPoint::New(x, y) => Some((x, y)),
+        }.and_then(|(x, y)| {
+            let xf = sm.level_transform(&level.name)?;
+            projection.world_to_screen(
+                camera_transform, window_size, xf.to_world(x, y).extend(xf.elevation))
+        });
+        if let Some(start_screen) = start_screen {
+            painter.line_segment([to_egui(start_screen), to_egui(screen)], stroke);
+        }
+    }
+
+    if !input_mouse.just_pressed(MouseButton::Left) || egui_context.ctx().wants_pointer_input() {
+        return;
+    }
+    // the first click of a double-click has done its work already
+    let now = time.seconds_since_startup();
+    let double_click = matches!(tools.last_click,
+        Some((then, at)) if now - then <= DOUBLE_CLICK_TIME && at.distance(cursor) <= SNAP_RADIUS);
+    tools.last_click = if double_click { None } else { Some((now, cursor)) };
+    if double_click {
+        if tools.tool == Tool::AddWall {
+            tools.start = None;
+        }
+        return;
+    }
+    let start = match tools.start {
+        None => {
+            tools.start = Some(point);
+            return;
+        }
+        Some(start) => start,
+    };
+    if start == point {
+        return;
+    }
+    if tools.tool == Tool::AddLane {
+        let lane = |e: Edge| Element::Lane(Lane::new(e.start, e.end));
+        let (edit, _) = connect(level, start, point, lane, level.lanes.len());
+        tools.start = None;
+        sm.apply(edit);
+    } else {
+        let (edit, end) = connect(level, start, point, Element::Wall, level.walls.len());
+        tools.start = Some(ToolPoint::Vertex(end));
+        sm.apply(edit);
+    }
+}
+
+#[derive(Default)]
+pub struct ToolsPlugin;
+
+impl Plugin for ToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<EditorTools>()
+           .add_system(draw_edges);
+    }
+}
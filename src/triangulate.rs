@@ -0,0 +1,243 @@
+// Ear-clipping triangulation of simple polygons with holes, used to turn
+// floor outlines into meshes.
+
+type Point = [f64; 2];
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a[0] - o[0]) * (b[1] - o[1]) - (a[1] - o[1]) * (b[0] - o[0])
+}
+
+fn signed_area(points: &[Point], ring: &[usize]) -> f64 {
+    let mut area = 0.;
+    for i in 0..ring.len() {
+        let a = points[ring[i]];
+        let b = points[ring[(i + 1) % ring.len()]];
+        area += a[0] * b[1] - b[0] * a[1];
+    }
+    area / 2.
+}
+
+fn same_point(a: Point, b: Point) -> bool {
+    (a[0] - b[0]).abs() < 1e-9 && (a[1] - b[1]).abs() < 1e-9
+}
+
+/// True if the open segments p1-p2 and q1-q2 cross each other.
+fn segments_cross(p1: Point, p2: Point, q1: Point, q2: Point) -> bool {
+    let d1 = cross(q1, q2, p1);
+    let d2 = cross(q1, q2, p2);
+    let d3 = cross(p1, p2, q1);
+    let d4 = cross(p1, p2, q2);
+    ((d1 > 0. && d2 < 0.) || (d1 < 0. && d2 > 0.))
+        && ((d3 > 0. && d4 < 0.) || (d3 < 0. && d4 > 0.))
+}
+
+/// True if `p` is inside the counter-clockwise triangle abc or on its
+/// boundary. A vertex on the cut that an ear would make blocks the ear too,
+/// as the cut would run along the outline there.
+fn inside_triangle(p: Point, a: Point, b: Point, c: Point) -> bool {
+    cross(a, b, p) >= 0. && cross(b, c, p) >= 0. && cross(c, a, p) >= 0.
+}
+
+/// Triangulates the polygon `outer` with the given `holes` cut out of it.
+///
+/// Returns triangles as counter-clockwise index triples into the
+/// concatenation of `outer` followed by every hole, in order.
+pub fn triangulate(outer: &[Point], holes: &[Vec<Point>]) -> Vec<[usize; 3]> {
+    let mut points: Vec<Point> = outer.to_vec();
+    let mut ring: Vec<usize> = (0..outer.len()).collect();
+    if ring.len() < 3 {
+        return Vec::new();
+    }
+    if signed_area(&points, &ring) < 0. {
+        ring.reverse();
+    }
+
+    let mut hole_rings = Vec::new();
+    for hole in holes {
+        if hole.len() < 3 {
+            continue;
+        }
+        let first = points.len();
+        points.extend_from_slice(hole);
+        let mut hole_ring: Vec<usize> = (first..points.len()).collect();
+        // holes wind the opposite way to the outline
+        if signed_area(&points, &hole_ring) > 0. {
+            hole_ring.reverse();
+        }
+        hole_rings.push(hole_ring);
+    }
+
+    // Bridge the holes into the outline, rightmost hole first, so that the
+    // result is a single (weakly) simple polygon.
+    hole_rings.sort_by(|a, b| {
+        let max_x = |r: &Vec<usize>| r.iter().map(|&i| points[i][0]).fold(f64::MIN, f64::max);
+        max_x(b).total_cmp(&max_x(a))
+    });
+    for h in 0..hole_rings.len() {
+        let hole = &hole_rings[h];
+        let (m_pos, &m) = hole.iter().enumerate()
+            .max_by(|a, b| points[*a.1][0].total_cmp(&points[*b.1][0]))
+            .unwrap();
+
+        let mut candidates: Vec<usize> = (0..ring.len()).collect();
+        candidates.sort_by(|&a, &b| {
+            let da = (points[ring[a]][0] - points[m][0]).powi(2) + (points[ring[a]][1] - points[m][1]).powi(2);
+            let db = (points[ring[b]][0] - points[m][0]).powi(2) + (points[ring[b]][1] - points[m][1]).powi(2);
+            da.total_cmp(&db)
+        });
+
+        let edges_of = |r: &Vec<usize>| -> Vec<(Point, Point)> {
+            (0..r.len()).map(|i| (points[r[i]], points[r[(i + 1) % r.len()]])).collect()
+        };
+        let mut obstacles = edges_of(&ring);
+        for other in &hole_rings[h..] {
+            obstacles.extend(edges_of(other));
+        }
+
+        let visible = candidates.into_iter().find(|&c| {
+            let v = points[ring[c]];
+            !obstacles.iter().any(|&(a, b)| segments_cross(points[m], v, a, b))
+        });
+        let c = match visible {
+            Some(c) => c,
+            None => continue,
+        };
+
+        let mut bridged = Vec::with_capacity(ring.len() + hole.len() + 2);
+        bridged.extend_from_slice(&ring[..=c]);
+        for k in 0..=hole.len() {
+            bridged.push(hole[(m_pos + k) % hole.len()]);
+        }
+        bridged.push(ring[c]);
+        bridged.extend_from_slice(&ring[c + 1..]);
+        ring = bridged;
+    }
+
+    let mut triangles = Vec::new();
+    while ring.len() > 3 {
+        let n = ring.len();
+        let ear = (0..n).find_map(|i| {
+            let (ia, ib, ic) = (ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]);
+            let (a, b, c) = (points[ia], points[ib], points[ic]);
+            let turn = cross(a, b, c);
+            if turn.abs() < 1e-12 {
+                // collinear: drop the middle vertex without emitting anything
+                return Some((i, None));
+            }
+            if turn < 0. {
+                return None;
+            }
+            let blocked = ring.iter().any(|&j| {
+                let p = points[j];
+                !same_point(p, a) && !same_point(p, b) && !same_point(p, c)
+                    && inside_triangle(p, a, b, c)
+            });
+            if blocked {
+                None
+            } else {
+                Some((i, Some([ia, ib, ic])))
+            }
+        });
+        match ear {
+            Some((i, triangle)) => {
+                triangles.extend(triangle);
+                ring.remove(i);
+            }
+            // numerically degenerate input; give up on the rest of it
+            None => break,
+        }
+    }
+    if ring.len() == 3 {
+        let (a, b, c) = (points[ring[0]], points[ring[1]], points[ring[2]]);
+        if cross(a, b, c) > 1e-12 {
+            triangles.push([ring[0], ring[1], ring[2]]);
+        }
+    }
+    triangles
+}
+
+/// Even-odd test of whether `p` lies inside `polygon`.
+pub fn contains(polygon: &[Point], p: Point) -> bool {
+    let mut inside = false;
+    for i in 0..polygon.len() {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a[1] > p[1]) != (b[1] > p[1])
+            && p[0] < a[0] + (p[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0])
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The total area of the triangles, checking that each is
+    /// counter-clockwise.
+    fn area(outer: &[Point], holes: &[Vec<Point>]) -> f64 {
+        let mut points = outer.to_vec();
+        for hole in holes {
+            points.extend_from_slice(hole);
+        }
+        triangulate(outer, holes).iter()
+            .map(|&[a, b, c]| {
+                let area = cross(points[a], points[b], points[c]) / 2.;
+                assert!(area > 0., "triangle {:?} is not counter-clockwise", [a, b, c]);
+                area
+            })
+            .sum()
+    }
+
+    fn square(x: f64, y: f64, size: f64) -> Vec<Point> {
+        vec![[x, y], [x + size, y], [x + size, y + size], [x, y + size]]
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn convex() {
+        assert_eq!(triangulate(&square(0., 0., 1.), &[]).len(), 2);
+        let mut clockwise = square(0., 0., 2.);
+        clockwise.reverse();
+        assert_close(area(&clockwise, &[]), 4.);
+    }
+
+    #[test]
+    fn concave() {
+        let l = [[0., 0.], [2., 0.], [2., 1.], [1., 1.], [1., 2.], [0., 2.]];
+        assert_close(area(&l, &[]), 3.);
+    }
+
+    #[test]
+    fn holes() {
+        let outer = square(0., 0., 10.);
+        assert_close(area(&outer, &[square(1., 1., 2.)]), 96.);
+        assert_close(area(&outer, &[square(1., 1., 2.), square(6., 6., 3.)]), 87.);
+    }
+
+    #[test]
+    fn collinear_and_duplicate_points() {
+        let outer = [[0., 0.], [1., 0.], [2., 0.], [2., 2.], [2., 2.], [0., 2.]];
+        assert_close(area(&outer, &[]), 4.);
+    }
+
+    #[test]
+    fn degenerate_input() {
+        assert!(triangulate(&[[0., 0.], [1., 0.]], &[]).is_empty());
+        assert!(triangulate(&[[0., 0.], [1., 0.], [2., 0.]], &[]).is_empty());
+        let outer = [[0., 0.], [1., 0.], [f64::NAN, 1.], [0., 1.]];
+        triangulate(&outer, &[square(0.2, 0.2, 0.2), vec![[f64::NAN, 0.5], [0.5, 0.6], [0.6, 0.6]]]);
+    }
+
+    #[test]
+    fn contains_points_inside_the_outline() {
+        let l = [[0., 0.], [2., 0.], [2., 1.], [1., 1.], [1., 2.], [0., 2.]];
+        assert!(contains(&l, [0.5, 1.5]));
+        assert!(!contains(&l, [1.5, 1.5]));
+    }
+}
@@ -0,0 +1,504 @@
+// Checks a building for mistakes that would break the editor, the exports or
+// the robots using its navigation graphs.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::building_map::{BuildingMap, ElementId, ElementKind, Level};
+use crate::nav_graph::{segments_intersect, Cabin};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something the exports cannot make sense of.
+    Error,
+    /// Something that is probably a mistake.
+    Warning,
+}
+
+/// One problem found in a building.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub level: String,
+    /// The element at fault, if it is one that can be selected.
+    pub element: Option<ElementId>,
+    /// Where the problem is, in the level's drawing coordinates.
+    pub location: Option<(f64, f64)>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", severity, self.level, self.message)
+    }
+}
+
+/// Every problem found in the building, errors first.
+pub fn validate(building: &BuildingMap) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for level in building.levels.values() {
+        let mut check = LevelCheck { level, diagnostics: &mut diagnostics };
+        check.dangling_indices();
+        check.zero_length_edges();
+        check.lanes_crossing_walls();
+        check.doors_off_lanes();
+    }
+    duplicate_vertex_names(building, &mut diagnostics);
+    unreachable_waypoints(building, &mut diagnostics);
+    diagnostics.sort_by_key(|d| d.severity);
+    diagnostics
+}
+
+struct LevelCheck<'a> {
+    level: &'a Level,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> LevelCheck<'a> {
+    fn report(
+        &mut self,
+        severity: Severity,
+        element: Option<(ElementKind, usize)>,
+        location: Option<(f64, f64)>,
+        message: String,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            level: self.level.name.clone(),
+            element: element.map(|(kind, index)| ElementId::new(&self.level.name, kind, index)),
+            location,
+            message,
+        });
+    }
+
+    fn position(&self, vertex: usize) -> Option<(f64, f64)> {
+        self.level.vertices.get(vertex).map(|v| (v.x, v.y))
+    }
+
+    /// The midpoint of an edge, or whichever of its ends exists.
+    fn midpoint(&self, start: usize, end: usize) -> Option<(f64, f64)> {
+        match (self.position(start), self.position(end)) {
+            (Some(a), Some(b)) => Some(((a.0 + b.0) / 2., (a.1 + b.1) / 2.)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The edges of the level, as (kind, index, start, end, what to call it).
+    fn edges(&self) -> Vec<(ElementKind, usize, usize, usize, &'static str)> {
+        let level = self.level;
+        let lanes = level.lanes.iter().enumerate()
+            .map(|(i, l)| (ElementKind::Lane, i, l.start, l.end, "lane"));
+        let walls = level.walls.iter().enumerate()
+            .map(|(i, w)| (ElementKind::Wall, i, w.start, w.end, "wall"));
+        let doors = level.doors.iter().enumerate()
+            .map(|(i, d)| (ElementKind::Door, i, d.start, d.end, "door"));
+        let measurements = level.measurements.iter().enumerate()
+            .map(|(i, m)| (ElementKind::Measurement, i, m.start, m.end, "measurement"));
+        lanes.chain(walls).chain(doors).chain(measurements).collect()
+    }
+
+    fn dangling_indices(&mut self) {
+        let num_vertices = self.level.vertices.len();
+        for (kind, i, start, end, what) in self.edges() {
+            for v in [start, end] {
+                if v >= num_vertices {
+                    let message = format!(
+                        "{} {} uses vertex {}, but the level only has {} vertices",
+                        what, i, v, num_vertices);
+                    let location = self.midpoint(start, end);
+                    self.report(Severity::Error, Some((kind, i)), location, message);
+                }
+            }
+        }
+        // holes cannot be selected, so they are reported without an element
+        let mut dangling = Vec::new();
+        for (what, outlines) in [("floor", &self.level.floors), ("hole", &self.level.holes)] {
+            for (i, outline) in outlines.iter().enumerate() {
+                let element = if what == "floor" { Some((ElementKind::Floor, i)) } else { None };
+                let location = outline.vertices.iter().find_map(|&v| self.position(v));
+                for &v in outline.vertices.iter().filter(|&&v| v >= num_vertices) {
+                    dangling.push((element, location, format!(
+                        "{} {} uses vertex {}, but the level only has {} vertices",
+                        what, i, v, num_vertices)));
+                }
+            }
+        }
+        for (element, location, message) in dangling {
+            self.report(Severity::Error, element, location, message);
+        }
+    }
+
+    fn zero_length_edges(&mut self) {
+        let scale = self.level.scale();
+        let edges: Vec<_> = self.edges().into_iter()
+            .filter(|(kind, ..)| matches!(kind, ElementKind::Lane | ElementKind::Wall))
+            .collect();
+        for (kind, i, start, end, what) in edges {
+            let (a, b) = match (self.position(start), self.position(end)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            let length = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt() * scale;
+            if start == end || length < 1e-3 {
+                let message = format!("{} {} has zero length", what, i);
+                self.report(Severity::Warning, Some((kind, i)), Some(a), message);
+            }
+        }
+    }
+
+    fn lanes_crossing_walls(&mut self) {
+        let mut crossings = Vec::new();
+        for (i, lane) in self.level.lanes.iter().enumerate() {
+            let (a, b) = match (self.position(lane.start), self.position(lane.end)) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            for (j, wall) in self.level.walls.iter().enumerate() {
+                let (c, d) = match (self.position(wall.start), self.position(wall.end)) {
+                    (Some(c), Some(d)) => (c, d),
+                    _ => continue,
+                };
+                if segments_intersect(a, b, c, d) {
+                    crossings.push((i, j, ((a.0 + b.0) / 2., (a.1 + b.1) / 2.)));
+                }
+            }
+        }
+        for (i, j, location) in crossings {
+            let message = format!("lane {} crosses wall {}", i, j);
+            self.report(Severity::Warning, Some((ElementKind::Lane, i)), Some(location), message);
+        }
+    }
+
+    fn doors_off_lanes(&mut self) {
+        let mut unused = Vec::new();
+        for (i, door) in self.level.doors.iter().enumerate() {
+            let (c, d) = match (self.position(door.start), self.position(door.end)) {
+                (Some(c), Some(d)) => (c, d),
+                _ => continue,
+            };
+            let crossed = self.level.lanes.iter().any(|lane| {
+                match (self.position(lane.start), self.position(lane.end)) {
+                    (Some(a), Some(b)) => segments_intersect(a, b, c, d),
+                    _ => false,
+                }
+            });
+            if !crossed {
+                unused.push((i, door.name.clone(), ((c.0 + d.0) / 2., (c.1 + d.1) / 2.)));
+            }
+        }
+        for (i, name, location) in unused {
+            let message = format!("door [{}] is not crossed by any lane", name);
+            self.report(Severity::Warning, Some((ElementKind::Door, i)), Some(location), message);
+        }
+    }
+}
+
+/// Waypoint names are shared by all levels, so they must be unique in the
+/// whole building.
+fn duplicate_vertex_names(building: &BuildingMap, diagnostics: &mut Vec<Diagnostic>) {
+    let mut seen: BTreeMap<&str, (&str, usize)> = BTreeMap::new();
+    for level in building.levels.values() {
+        for (i, v) in level.vertices.iter().enumerate().filter(|(_, v)| !v.name.is_empty()) {
+            match seen.get(v.name.as_str()) {
+                Some((first_level, first)) => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    level: level.name.clone(),
+                    element: Some(ElementId::new(&level.name, ElementKind::Vertex, i)),
+                    location: Some((v.x, v.y)),
+                    message: format!(
+                        "vertex {} is named [{}], like vertex {} of level {}",
+                        i, v.name, first, first_level),
+                }),
+                None => {
+                    seen.insert(&v.name, (&level.name, i));
+                }
+            }
+        }
+    }
+}
+
+/// In each graph, waypoints that robots cannot drive to from the rest of
+/// the graph and back again. The largest group of waypoints that can all
+/// reach each other is taken to be the rest of the graph. Waypoints inside
+/// the same lift cabin are connected across levels.
+fn unreachable_waypoints(building: &BuildingMap, diagnostics: &mut Vec<Diagnostic>) {
+    let cabins: Vec<Cabin> = building.lifts.values()
+        .filter_map(|lift| Cabin::new(lift, building))
+        .collect();
+    let graph_indices: BTreeSet<i64> = building.levels.values()
+        .flat_map(|level| level.lanes.iter().map(|lane| lane.graph_idx))
+        .collect();
+
+    for graph_idx in graph_indices {
+        // nodes are (level, vertex) pairs
+        let mut nodes: Vec<(&str, usize)> = Vec::new();
+        let mut node_of: BTreeMap<(&str, usize), usize> = BTreeMap::new();
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        for level in building.levels.values() {
+            let lanes = level.lanes.iter()
+                .filter(|lane| lane.graph_idx == graph_idx)
+                .filter(|lane| lane.start < level.vertices.len() && lane.end < level.vertices.len());
+            for lane in lanes {
+                let mut node = |v: usize| *node_of.entry((&level.name, v)).or_insert_with(|| {
+                    nodes.push((&level.name, v));
+                    nodes.len() - 1
+                });
+                let (a, b) = (node(lane.start), node(lane.end));
+                edges.push((a, b));
+                if lane.bidirectional {
+                    edges.push((b, a));
+                }
+            }
+        }
+        for cabin in &cabins {
+            let inside: Vec<usize> = nodes.iter().enumerate()
+                .filter(|(_, (level, v))| {
+                    let level = &building.levels[*level];
+                    let scale = level.scale();
+                    let v = &level.vertices[*v];
+                    cabin.contains(&level.name, (v.x * scale, -v.y * scale))
+                })
+                .map(|(i, _)| i)
+                .collect();
+            for &a in &inside {
+                for &b in &inside {
+                    if a != b {
+                        edges.push((a, b));
+                    }
+                }
+            }
+        }
+
+        let components = strongly_connected_components(nodes.len(), &edges);
+        let mut sizes = BTreeMap::new();
+        for &c in &components {
+            *sizes.entry(c).or_insert(0) += 1;
+        }
+        let main = match sizes.iter().max_by_key(|(_, size)| **size) {
+            Some((&main, _)) => main,
+            None => continue,
+        };
+        for (node, &(level_name, v)) in nodes.iter().enumerate() {
+            if components[node] == main {
+                continue;
+            }
+            let vertex = &building.levels[level_name].vertices[v];
+            let name = if vertex.name.is_empty() {
+                format!("vertex {}", v)
+            } else {
+                format!("waypoint [{}]", vertex.name)
+            };
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                level: level_name.to_string(),
+                element: Some(ElementId::new(level_name, ElementKind::Vertex, v)),
+                location: Some((vertex.x, vertex.y)),
+                message: format!(
+                    "{} of graph {} cannot be reached from the rest of the graph and back",
+                    name, graph_idx),
+            });
+        }
+    }
+}
+
+/// Labels each node with its strongly connected component, using
+/// Kosaraju's algorithm.
+fn strongly_connected_components(num_nodes: usize, edges: &[(usize, usize)]) -> Vec<usize> {
+    let mut forward = vec![Vec::new(); num_nodes];
+    let mut backward = vec![Vec::new(); num_nodes];
+    for &(a, b) in edges {
+        forward[a].push(b);
+        backward[b].push(a);
+    }
+
+    // order the nodes by when their depth-first search finishes
+    let mut visited = vec![false; num_nodes];
+    let mut order = Vec::with_capacity(num_nodes);
+    for root in 0..num_nodes {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+        let mut stack = vec![(root, 0)];
+        while let Some((node, next)) = stack.pop() {
+            match forward[node].get(next) {
+                Some(&child) => {
+                    stack.push((node, next + 1));
+                    if !visited[child] {
+                        visited[child] = true;
+                        stack.push((child, 0));
+                    }
+                }
+                None => order.push(node),
+            }
+        }
+    }
+
+    // then collect components on the reversed graph, latest finish first
+    let unassigned = usize::MAX;
+    let mut component = vec![unassigned; num_nodes];
+    for (label, &root) in order.iter().rev().enumerate() {
+        if component[root] != unassigned {
+            continue;
+        }
+        component[root] = label;
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            for &parent in &backward[node] {
+                if component[parent] == unassigned {
+                    component[parent] = label;
+                    stack.push(parent);
+                }
+            }
+        }
+    }
+    component
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `validate` says about a building with one level, L1, whose
+    /// sections are the lines of `level`.
+    fn check(level: &str) -> Vec<String> {
+        let level: String = level.lines().map(|line| format!("    {}\n", line.trim())).collect();
+        check_building(&format!("name: test\nlevels:\n  L1:\n    elevation: 0\n{}", level))
+    }
+
+    fn check_building(yaml: &str) -> Vec<String> {
+        let building = BuildingMap::from_yaml(yaml).unwrap();
+        validate(&building).iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn a_good_building_has_no_diagnostics() {
+        let messages = check("\
+    vertices: [[0, 0, 0, a], [100, 0, 0, b], [50, -50, 0, ''], [50, 50, 0, '']]
+    lanes: [[0, 1, {bidirectional: [4, true]}]]
+    doors: [[2, 3, {name: [1, door1], type: [1, hinged]}]]
+");
+        assert_eq!(messages, Vec::<String>::new());
+    }
+
+    #[test]
+    fn dangling_indices() {
+        let messages = check("\
+    vertices: [[0, 0, 0, ''], [100, 0, 0, '']]
+    lanes: [[0, 5, {bidirectional: [4, true]}]]
+    floors: [{vertices: [0, 1, 9]}]
+    holes: [{vertices: [0, 1, 7]}]
+");
+        assert_eq!(messages, vec![
+            "error: L1: lane 0 uses vertex 5, but the level only has 2 vertices",
+            "error: L1: floor 0 uses vertex 9, but the level only has 2 vertices",
+            "error: L1: hole 0 uses vertex 7, but the level only has 2 vertices",
+        ]);
+    }
+
+    #[test]
+    fn dangling_floors_can_be_selected() {
+        let building = BuildingMap::from_yaml("\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices: [[0, 0, 0, ''], [100, 0, 0, '']]
+    floors: [{vertices: [0, 1, 9]}]
+    holes: [{vertices: [0, 1, 7]}]
+").unwrap();
+        let diagnostics = validate(&building);
+        assert_eq!(diagnostics[0].element, Some(ElementId::new("L1", ElementKind::Floor, 0)));
+        assert_eq!(diagnostics[1].element, None);
+    }
+
+    #[test]
+    fn zero_length_lanes_and_walls() {
+        let messages = check("\
+    vertices: [[0, 0, 0, ''], [0, 0, 0, '']]
+    lanes: [[0, 0, {bidirectional: [4, true]}]]
+    walls: [[0, 1, {}]]
+");
+        assert_eq!(messages, vec![
+            "warning: L1: lane 0 has zero length",
+            "warning: L1: wall 0 has zero length",
+        ]);
+    }
+
+    #[test]
+    fn lane_crossing_a_wall() {
+        let messages = check("\
+    vertices: [[0, 0, 0, ''], [100, 0, 0, ''], [50, -50, 0, ''], [50, 50, 0, '']]
+    lanes: [[0, 1, {bidirectional: [4, true]}]]
+    walls: [[2, 3, {}]]
+");
+        assert_eq!(messages, vec!["warning: L1: lane 0 crosses wall 0"]);
+    }
+
+    #[test]
+    fn door_crossed_by_no_lane() {
+        let messages = check("\
+    vertices: [[0, 0, 0, ''], [100, 0, 0, ''], [50, 50, 0, ''], [50, 150, 0, '']]
+    lanes: [[0, 1, {bidirectional: [4, true]}]]
+    doors: [[2, 3, {name: [1, door1], type: [1, hinged]}]]
+");
+        assert_eq!(messages, vec!["warning: L1: door [door1] is not crossed by any lane"]);
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let messages = check_building("\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices: [[0, 0, 0, a]]
+  L2:
+    elevation: 3
+    vertices: [[0, 0, 0, a]]
+");
+        assert_eq!(messages, vec!["error: L2: vertex 0 is named [a], like vertex 0 of level L1"]);
+    }
+
+    /// Two levels joined by a lift, with a one-way lane into a dead end on
+    /// the first. The far side of the lift can be reached; the dead end
+    /// cannot be left.
+    #[test]
+    fn unreachable_waypoints_across_lifts() {
+        let building = "\
+name: test
+levels:
+  L1:
+    elevation: 0
+    vertices: [[0, 0, 0, a], [100, 0, 0, b], [300, 0, 0, lift_l1], [100, 100, 0, dead_end]]
+    lanes:
+      - [0, 1, {bidirectional: [4, true]}]
+      - [1, 2, {bidirectional: [4, true]}]
+      - [1, 3, {bidirectional: [4, false]}]
+  L2:
+    elevation: 3
+    vertices: [[300, 0, 0, lift_l2], [400, 0, 0, c]]
+    lanes: [[0, 1, {bidirectional: [4, true]}]]
+lifts:
+  lift1:
+    depth: 1
+    width: 1
+    x: 300
+    y: 0
+    reference_floor_name: L1
+    level_doors: {L1: [], L2: []}
+";
+        assert_eq!(check_building(building), vec![
+            "warning: L1: waypoint [dead_end] of graph 0 cannot be reached from the rest of the graph and back",
+        ]);
+
+        let without_lift = &building[..building.find("lifts:").unwrap()];
+        let messages = check_building(without_lift);
+        assert_eq!(messages.len(), 3);
+        assert!(messages.iter().any(|m| m.contains("waypoint [c]")));
+    }
+}
@@ -0,0 +1,239 @@
+use bevy::{
+    core::Time,
+    ecs::prelude::*,
+    input::{
+        keyboard::KeyCode,
+        mouse::{MouseButton, MouseMotion},
+        Input,
+    },
+    math::{Quat, Vec2, Vec3},
+    prelude::{App, Plugin, Transform},
+};
+use bevy_egui::EguiContext;
+
+use crate::geometry::WALL_THICKNESS;
+use crate::site_map::SiteMap;
+use crate::supercamera::{FlexibleProjection, MouseCaptured, ProjectionMode, SUPERCAMERA_MOTION};
+
+/// How close, in metres, the camera may come to the side of a wall.
+const CAMERA_RADIUS: f32 = 0.2;
+
+/// Radians the view turns per pixel of mouse motion.
+const LOOK_SPEED: f32 = 0.005;
+
+/// How the walkthrough camera moves.
+pub struct WalkthroughSettings {
+    /// Height of the camera above the floor, in metres, like a robot's
+    /// sensor.
+    pub sensor_height: f32,
+    /// Walking speed, in metres per second.
+    pub speed: f32,
+}
+
+impl Default for WalkthroughSettings {
+    fn default() -> Self {
+        WalkthroughSettings {
+            sensor_height: 0.5,
+            speed: 1.5,
+        }
+    }
+}
+
+/// The level, and its elevation, that a walker with its feet at height `z`
+/// stands on: the highest one below, or barely above, `z`, or the lowest if
+/// `z` is below them all.
+fn level_at(sm: &SiteMap, z: f32) -> Option<(String, f32)> {
+    let mut levels: Vec<(String, f32)> = sm.levels().keys()
+        .filter_map(|name| Some((name.clone(), sm.level_transform(name)?.elevation)))
+        .collect();
+    levels.sort_by(|a, b| a.1.total_cmp(&b.1));
+    levels.iter().rev().find(|(_, elevation)| *elevation <= z + 0.25).or_else(|| levels.first()).cloned()
+}
+
+/// The camera's rotation for looking along a heading (anticlockwise from
+/// the x axis) and pitch (up is positive).
+fn look(yaw: f32, pitch: f32) -> Quat {
+    // the camera looks along its -z axis with y up: turn that to look along
+    // +y with z up, then pitch and turn to the heading
+    Quat::from_rotation_z(yaw - std::f32::consts::FRAC_PI_2)
+        * Quat::from_rotation_x(std::f32::consts::FRAC_PI_2 + pitch)
+}
+
+/// The walls of a level as world-frame segments.
+fn wall_segments(sm: &SiteMap, level_name: &str) -> Vec<(Vec2, Vec2)> {
+    let (level, xf) = match (sm.levels().get(level_name), sm.level_transform(level_name)) {
+        (Some(level), Some(xf)) => (level, xf),
+        _ => return Vec::new(),
+    };
+    let position = |i: usize| level.vertices.get(i).map(|v| xf.to_world(v.x, v.y));
+    level.walls.iter()
+        .filter_map(|wall| Some((position(wall.start)?, position(wall.end)?)))
+        .collect()
+}
+
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let span = b - a;
+    let along = ((p - a).dot(span) / span.length_squared().max(1e-9)).clamp(0., 1.);
+    p.distance(a + span * along)
+}
+
+/// How far the walker may get from `from` toward `to` without entering a
+/// wall: all the way, or sliding along the wall on one axis, or not at all.
+/// A walker that already stands too close may still move away.
+fn collide(from: Vec2, to: Vec2, walls: &[(Vec2, Vec2)]) -> Vec2 {
+    let clearance = |p: Vec2| -> f32 {
+        walls.iter().map(|(a, b)| distance_to_segment(p, *a, *b)).fold(f32::INFINITY, f32::min)
+    };
+    let needed = f32::min(CAMERA_RADIUS + WALL_THICKNESS as f32 / 2., clearance(from));
+    [to, Vec2::new(to.x, from.y), Vec2::new(from.x, to.y)]
+        .iter()
+        .copied()
+        .find(|p| clearance(*p) >= needed)
+        .unwrap_or(from)
+}
+
+/// In walkthrough mode, WASD walks across the level and dragging with the
+/// right mouse button looks around, keeping the camera at the sensor height
+/// and out of the walls. The walker's pose is the camera's, so that other
+/// systems can move it.
+fn walkthrough_motion(
+    sm: Res<SiteMap>,
+    settings: Res<WalkthroughSettings>,
+    time: Res<Time>,
+    keyboard_input: Res<Input<KeyCode>>,
+    input_mouse: Res<Input<MouseButton>>,
+    mut ev_motion: EventReader<MouseMotion>,
+    mouse_captured: Res<MouseCaptured>,
+    egui_context: Res<EguiContext>,
+    mut walking: Local<bool>,
+    mut query: Query<(&mut Transform, &FlexibleProjection)>,
+) {
+    let (mut transform, projection) = query.single_mut();
+    if projection.mode != ProjectionMode::Walkthrough {
+        *walking = false;
+        return;
+    }
+
+    let forward = transform.rotation * -Vec3::Z;
+    let (mut yaw, mut pitch) = (forward.y.atan2(forward.x), forward.z.clamp(-1., 1.).asin());
+    let level = if *walking {
+        level_at(&sm, transform.translation.z - settings.sensor_height)
+    } else {
+        // start where the camera was looking, facing the way it faced, or
+        // up the screen if it looked straight down
+        let center = projection.orbit_center();
+        transform.translation = center;
+        if forward.truncate().length() < 0.1 {
+            let up = transform.rotation * Vec3::Y;
+            yaw = up.y.atan2(up.x);
+            pitch = 0.;
+        }
+        *walking = true;
+        level_at(&sm, center.z)
+    };
+    let (level, elevation) = match level {
+        Some(level) => level,
+        None => return,
+    };
+
+    let mut motion = Vec2::ZERO;
+    for ev in ev_motion.iter() {
+        motion += ev.delta;
+    }
+    if input_mouse.pressed(MouseButton::Right) && !mouse_captured.0 {
+        yaw -= motion.x * LOOK_SPEED;
+        pitch = (pitch - motion.y * LOOK_SPEED).clamp(-1.4, 1.4);
+    }
+
+    let mut position = transform.translation.truncate();
+    if !egui_context.ctx().wants_keyboard_input() {
+        let ahead = Vec2::new(yaw.cos(), yaw.sin());
+        let right = Vec2::new(ahead.y, -ahead.x);
+        let mut step = Vec2::ZERO;
+        for (key, direction) in [(KeyCode::W, ahead), (KeyCode::S, -ahead), (KeyCode::D, right), (KeyCode::A, -right)] {
+            if keyboard_input.pressed(key) {
+                step += direction;
+            }
+        }
+        if step.length_squared() > 0. {
+            // a long frame should not carry the walker through a wall
+            let dt = time.delta_seconds().min(0.1);
+            let to = position + step.normalize() * settings.speed * dt;
+            position = collide(position, to, &wall_segments(&sm, &level));
+        }
+    }
+
+    transform.translation = position.extend(elevation + settings.sensor_height);
+    transform.rotation = look(yaw, pitch);
+}
+
+#[derive(Default)]
+pub struct WalkthroughPlugin;
+
+impl Plugin for WalkthroughPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WalkthroughSettings>()
+           // after the camera has handled any switch of projection mode
+           .add_system(walkthrough_motion.after(SUPERCAMERA_MOTION));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A wall along the x axis, from x = -5 to 5.
+    fn wall() -> Vec<(Vec2, Vec2)> {
+        vec![(Vec2::new(-5., 0.), Vec2::new(5., 0.))]
+    }
+
+    #[test]
+    fn distance_to_segments() {
+        let (a, b) = (Vec2::new(0., 0.), Vec2::new(4., 0.));
+        assert_eq!(distance_to_segment(Vec2::new(2., 3.), a, b), 3.);
+        assert_eq!(distance_to_segment(Vec2::new(7., 4.), a, b), 5.);
+        assert_eq!(distance_to_segment(Vec2::new(3., 4.), a, a), 5.);
+    }
+
+    #[test]
+    fn a_step_into_a_wall_is_refused() {
+        let from = Vec2::new(0., -0.5);
+        assert_eq!(collide(from, Vec2::new(0., -0.1), &wall()), from);
+        // away from the wall is fine
+        assert_eq!(collide(from, Vec2::new(0., -0.8), &wall()), Vec2::new(0., -0.8));
+    }
+
+    #[test]
+    fn a_diagonal_step_slides_along_the_wall() {
+        let from = Vec2::new(0., -0.5);
+        assert_eq!(collide(from, Vec2::new(0.3, -0.1), &wall()), Vec2::new(0.3, -0.5));
+    }
+
+    #[test]
+    fn a_walker_too_close_can_move_away() {
+        let from = Vec2::new(0., -0.1);
+        assert_eq!(collide(from, Vec2::new(0., -0.3), &wall()), Vec2::new(0., -0.3));
+        assert_eq!(collide(from, Vec2::new(0.2, -0.1), &wall()), Vec2::new(0.2, -0.1));
+        assert_eq!(collide(from, Vec2::new(0., -0.05), &wall()), from);
+    }
+
+    #[test]
+    fn the_walker_stands_on_the_level_below_it() {
+        let mut sm = SiteMap::default();
+        sm.load_yaml("\
+name: test
+levels:
+  L1:
+    elevation: 0
+  L2:
+    elevation: 3
+").unwrap();
+        let level = |z: f32| level_at(&sm, z).unwrap();
+        assert_eq!(level(1.), ("L1".to_string(), 0.));
+        assert_eq!(level(5.), ("L2".to_string(), 3.));
+        // stepping up onto the next level
+        assert_eq!(level(2.9), ("L2".to_string(), 3.));
+        // below them all
+        assert_eq!(level(-1.), ("L1".to_string(), 0.));
+    }
+}